@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use nightmaregl::events::{Key, KeyState};
 use nightmaregl::{Context, Size};
@@ -5,12 +7,31 @@ use nightmaregl::{Context, Size};
 use crate::canvas::Canvas;
 use crate::commands::{Command, CommandInput};
 use crate::input::InputHandler;
+use crate::selection::Visual;
+
+// -----------------------------------------------------------------------------
+//     - Insert -
+//     What insert mode does to the pixels under the brush
+//     * Paint (i): paint on every key press
+//     * Replace (R): only paint when moving
+//     * Append (a): move one pixel to the right, then paint like `i`
+//     * Once (s): paint once and go back to normal mode
+//     * Erase (x): clear pixels back to transparent
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Insert {
+    Paint,
+    Replace,
+    Append,
+    Once,
+    Erase,
+}
 
 #[derive(Debug, Copy, Clone)]
 pub enum Mode {
-    Insert,
+    Insert(Insert),
     Normal,
-    Visual,
+    Visual(Visual),
     Command,
 }
 
@@ -21,6 +42,31 @@ impl Mode {
             _ => false,
         }
     }
+
+    pub fn visual_mode(&self) -> bool {
+        match self {
+            Mode::Visual(_) => true,
+            _ => false,
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Label -
+    //     Shown in the mode indicator
+    // -----------------------------------------------------------------------------
+    pub fn label(&self) -> &'static str {
+        match self {
+            Mode::Insert(Insert::Paint) => "-- INSERT --",
+            Mode::Insert(Insert::Replace) => "-- REPLACE --",
+            Mode::Insert(Insert::Append) => "-- APPEND --",
+            Mode::Insert(Insert::Once) => "-- INSERT (once) --",
+            Mode::Insert(Insert::Erase) => "-- ERASE --",
+            Mode::Visual(Visual::Rect) => "-- VISUAL --",
+            Mode::Visual(Visual::Line) => "-- VISUAL LINE --",
+            Mode::Visual(Visual::Block) => "-- VISUAL BLOCK --",
+            Mode::Normal | Mode::Command => "",
+        }
+    }
 }
 
 pub struct App {
@@ -28,6 +74,9 @@ pub struct App {
     input: InputHandler,
     command_input: CommandInput,
     canvas: Canvas,
+    // The key that ended a command line (Enter / Esc) is not passed on,
+    // otherwise Enter would also commit and Esc would also cancel
+    consumed: bool,
 }
 
 impl App {
@@ -54,6 +103,7 @@ impl App {
             input,
             command_input,
             canvas,
+            consumed: false,
         };
 
         Ok(inst)
@@ -63,22 +113,34 @@ impl App {
 
     pub fn update_input(&mut self, c: char, context: &mut Context) -> Command {
         self.input.update(c);
+        self.canvas.clear_message();
 
         match self.mode {
             Mode::Normal => {
                 // Possibly enter insert mode
                 // Possibly enter visual mode
                 match c {
-                    'i' => self.mode = Mode::Insert,
-                    ':' => self.mode = Mode::Command,
+                    'i' => self.mode = Mode::Insert(Insert::Paint),
+                    'R' => self.mode = Mode::Insert(Insert::Replace),
+                    'a' => self.mode = Mode::Insert(Insert::Append),
+                    's' => self.mode = Mode::Insert(Insert::Once),
+                    'x' => self.mode = Mode::Insert(Insert::Erase),
+                    'v' => self.mode = Mode::Visual(Visual::Rect),
+                    'V' => self.mode = Mode::Visual(Visual::Line),
+                    // Ctrl-v
+                    '\u{16}' => self.mode = Mode::Visual(Visual::Block),
+                    ':' | '/' => self.mode = Mode::Command,
                     _ => {}
                 }
             }
-            Mode::Visual => {
+            Mode::Visual(_) => {
                 // Possibly enter insert mode
                 // Possibly back to normal mode
                 match c {
                     ':' => self.mode = Mode::Command,
+                    'v' => self.mode = Mode::Visual(Visual::Rect),
+                    'V' => self.mode = Mode::Visual(Visual::Line),
+                    '\u{16}' => self.mode = Mode::Visual(Visual::Block),
                     // Esc
                     '\u{1b}' => {
                         self.canvas.input(c, self.mode, &self.input);
@@ -87,7 +149,7 @@ impl App {
                     _ => {}
                 }
             }
-            Mode::Insert => {
+            Mode::Insert(_) => {
                 // Esc will change back to normal
                 match c {
                     // Esc
@@ -110,11 +172,13 @@ impl App {
                         }
                         self.canvas.exec(command, context);
                         self.mode = Mode::Normal;
+                        self.consumed = true;
                     }
                     // Esc
                     '\u{1b}' => {
                         self.command_input.input(c, self.mode, &self.input);
                         self.mode = Mode::Normal;
+                        self.consumed = true;
                     }
                     _ => {}
                 }
@@ -124,6 +188,12 @@ impl App {
         Command::Noop
     }
 
+    // Called once per frame with the time since the last one
+    pub fn update(&mut self, dt: Duration) {
+        self.canvas.update(dt);
+        self.update_indicator();
+    }
+
     pub fn render(&mut self, context: &mut Context) {
         self.canvas.render(context);
         self.command_input.render(context, self.mode);
@@ -134,7 +204,46 @@ impl App {
     }
 
     pub fn input(&mut self, c: char) {
+        if std::mem::take(&mut self.consumed) {
+            return self.update_indicator();
+        }
+
         self.command_input.input(c, self.mode, &self.input);
         self.canvas.input(c, self.mode, &self.input);
+
+        // Paint once: straight back to normal mode
+        if let Mode::Insert(Insert::Once) = self.mode {
+            self.mode = Mode::Normal;
+        }
+
+        // An operator (yank, delete, fill...) used up the selection
+        if self.mode.visual_mode() && !self.canvas.selecting() {
+            self.mode = Mode::Normal;
+        }
+
+        // So the canvas sees the next mode change as it happens
+        self.canvas.set_mode(self.mode);
+
+        self.update_indicator();
+    }
+
+    fn update_indicator(&mut self) {
+        if let Some(message) = self.canvas.message() {
+            let message = message.to_string();
+            self.command_input.set_indicator(&message);
+            return;
+        }
+
+        let mut label = self.mode.label().to_string();
+        if let (Mode::Insert(_), false) = (self.mode, self.canvas.pen_down()) {
+            label.push_str(" (pen up)");
+        }
+        if let Some(frame) = self.canvas.frame_label() {
+            if !label.is_empty() {
+                label.push(' ');
+            }
+            label.push_str(&frame);
+        }
+        self.command_input.set_indicator(&label);
     }
 }
@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use nightmaregl::{Pixel, Size};
+
+use crate::frame::{Direction, Tag};
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const OLD_PALETTE_CHUNK: u16 = 0x0004;
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+
+// Larger sprites and cels are refused rather than allocated
+const MAX_SIZE: i32 = 4096;
+
+// -----------------------------------------------------------------------------
+//     - Document -
+//     What mixel keeps of an Aseprite file: image layers, frames (one pixel
+//     buffer per layer, in reading order), linked cels and tags.
+//     The palette is only read to decode indexed cels, and written from the
+//     colours in use. Groups, blend modes, slices and user data are not kept.
+// -----------------------------------------------------------------------------
+pub struct Document {
+    pub size: Size<i32>,
+    pub layer_names: Vec<String>,
+    pub frames: Vec<DocumentFrame>,
+    pub tags: Vec<Tag>,
+}
+
+// Cels with the same link on the same layer are linked
+pub struct DocumentFrame {
+    pub layers: Vec<Vec<Pixel>>,
+    pub links: Vec<Option<usize>>,
+    pub duration: u32,
+}
+
+pub fn is_aseprite(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("ase") || ext.eq_ignore_ascii_case("aseprite"),
+        None => false,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Reader -
+//     Little endian, as in the file format spec
+// -----------------------------------------------------------------------------
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("unexpected end of file"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn short(&mut self) -> Result<i16> {
+        Ok(self.word()? as i16)
+    }
+
+    fn dword(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.word()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Read -
+// -----------------------------------------------------------------------------
+struct Layer {
+    name: String,
+    image: bool,
+    background: bool,
+    opacity: u8,
+}
+
+#[derive(Clone)]
+struct Cel {
+    x: i32,
+    y: i32,
+    opacity: u8,
+    size: Size<i32>,
+    pixels: Vec<Pixel>,
+}
+
+pub fn read(path: &Path) -> Result<Document> {
+    let data = fs::read(path)?;
+    let mut r = Reader::new(&data);
+
+    r.dword()?;
+    if r.word()? != HEADER_MAGIC {
+        bail!("not an aseprite file: {}", path.display());
+    }
+
+    let frame_count = r.word()?;
+    let size = Size::new(r.word()? as i32, r.word()? as i32);
+    let depth = r.word()?;
+    let layer_opacity = r.dword()? & 1 != 0;
+    r.skip(2 + 4 + 4)?;
+    let transparent = r.byte()?;
+    r.skip(3 + 2 + 1 + 1 + 2 + 2 + 2 + 2 + 84)?;
+
+    if ![8, 16, 32].contains(&depth) {
+        bail!("unsupported colour depth: {}", depth);
+    }
+
+    if !valid_size(size) {
+        bail!("unsupported size: {}x{}", size.width, size.height);
+    }
+
+    let mut layers = Vec::<Layer>::new();
+    let mut palette = vec![Pixel::transparent(); 256];
+    let mut tags = Vec::new();
+    let mut cels = Vec::<HashMap<usize, Cel>>::new();
+    let mut durations = Vec::new();
+
+    // (frame, layer) of the first cel in a group of linked cels, and the link.
+    // Every cel in the group points at the same first cel.
+    let mut links = HashMap::<(usize, usize), usize>::new();
+    let mut linked = HashMap::<(usize, usize), usize>::new();
+
+    for frame in 0..frame_count as usize {
+        let start = r.pos;
+        let frame_size = r.dword()? as usize;
+        if r.word()? != FRAME_MAGIC {
+            bail!("bad frame header in frame {}", frame);
+        }
+        let old_chunks = r.word()?;
+        durations.push(r.word()? as u32);
+        r.skip(2)?;
+        let chunks = match r.dword()? {
+            0 => old_chunks as u32,
+            n => n,
+        };
+
+        // Cels are decoded once all chunks of the frame are read,
+        // as the palette may come after them.
+        let mut raw_cels = Vec::new();
+
+        for _ in 0..chunks {
+            let chunk_size = r.dword()? as usize;
+            let kind = r.word()?;
+            let mut chunk = Reader::new(r.bytes(chunk_size.saturating_sub(6))?);
+
+            match kind {
+                OLD_PALETTE_CHUNK => read_old_palette(&mut chunk, &mut palette)?,
+                PALETTE_CHUNK => read_palette(&mut chunk, &mut palette)?,
+                LAYER_CHUNK => layers.push(read_layer(&mut chunk)?),
+                TAGS_CHUNK => tags = read_tags(&mut chunk, frame_count as usize)?,
+                CEL_CHUNK => raw_cels.push(chunk),
+                _ => {}
+            }
+        }
+
+        let mut frame_cels = HashMap::new();
+        for mut chunk in raw_cels {
+            let layer = chunk.word()? as usize;
+            let x = chunk.short()? as i32;
+            let y = chunk.short()? as i32;
+            let opacity = chunk.byte()?;
+            let kind = chunk.word()?;
+            chunk.skip(2 + 5)?;
+
+            let background = layers.get(layer).map(|l| l.background).unwrap_or(false);
+            let to_pixel = |b: &[u8]| match depth {
+                32 => Pixel { r: b[0], g: b[1], b: b[2], a: b[3] },
+                16 => Pixel { r: b[0], g: b[0], b: b[0], a: b[1] },
+                _ if b[0] == transparent && !background => Pixel::transparent(),
+                _ => palette[b[0] as usize],
+            };
+
+            let cel = match kind {
+                // Raw and compressed image
+                0 | 2 => {
+                    let size = Size::new(chunk.word()? as i32, chunk.word()? as i32);
+                    if !valid_size(size) {
+                        bail!("bad cel size in frame {}: {}x{}", frame, size.width, size.height);
+                    }
+
+                    let bytes = match kind {
+                        0 => chunk.rest().to_vec(),
+                        _ => {
+                            let mut bytes = Vec::new();
+                            ZlibDecoder::new(chunk.rest()).read_to_end(&mut bytes)?;
+                            bytes
+                        }
+                    };
+
+                    let pixels = bytes
+                        .chunks_exact(depth as usize / 8)
+                        .map(to_pixel)
+                        .collect::<Vec<_>>();
+                    if pixels.len() != (size.width * size.height) as usize {
+                        bail!("bad cel data in frame {}", frame);
+                    }
+
+                    Cel { x, y, opacity, size, pixels }
+                }
+                // Linked: the same cel as in an earlier frame
+                1 => {
+                    let first = chunk.word()? as usize;
+                    let cel = match cels.get(first).and_then(|c| c.get(&layer)) {
+                        Some(cel) => cel.clone(),
+                        None => continue,
+                    };
+
+                    let next = links.len() + 1;
+                    let link = *links.entry((first, layer)).or_insert(next);
+                    linked.insert((first, layer), link);
+                    linked.insert((frame, layer), link);
+                    cel
+                }
+                // Tilemaps are not supported
+                _ => continue,
+            };
+
+            frame_cels.insert(layer, cel);
+        }
+
+        cels.push(frame_cels);
+        r.pos = start + frame_size;
+    }
+
+    // Only image layers are kept, so the layer index in a cel
+    // has to be mapped to the index in mixel.
+    let image_layers = layers
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| l.image)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let frames = cels
+        .into_iter()
+        .zip(durations)
+        .enumerate()
+        .map(|(frame, (frame_cels, duration))| {
+            let layers = image_layers
+                .iter()
+                .map(|index| {
+                    let mut pixels = vec![Pixel::transparent(); (size.width * size.height) as usize];
+                    if let Some(cel) = frame_cels.get(index) {
+                        let opacity = match layer_opacity {
+                            true => cel.opacity as u32 * layers[*index].opacity as u32 / 255,
+                            false => cel.opacity as u32,
+                        };
+                        draw_cel(&mut pixels, size, cel, opacity);
+                    }
+                    pixels
+                })
+                .collect();
+
+            let links = image_layers
+                .iter()
+                .map(|index| linked.get(&(frame, *index)).copied())
+                .collect();
+
+            DocumentFrame {
+                layers,
+                links,
+                duration,
+            }
+        })
+        .collect();
+
+    let layer_names = image_layers.iter().map(|i| layers[*i].name.clone()).collect();
+
+    Ok(Document {
+        size,
+        layer_names,
+        frames,
+        tags,
+    })
+}
+
+fn valid_size(size: Size<i32>) -> bool {
+    size.width > 0 && size.height > 0 && size.width <= MAX_SIZE && size.height <= MAX_SIZE
+}
+
+fn draw_cel(pixels: &mut [Pixel], size: Size<i32>, cel: &Cel, opacity: u32) {
+    for (i, pixel) in cel.pixels.iter().enumerate() {
+        let i = i as i32;
+        let x = cel.x + i % cel.size.width;
+        let y = cel.y + i / cel.size.width;
+        if x < 0 || y < 0 || x >= size.width || y >= size.height {
+            continue;
+        }
+
+        let a = (pixel.a as u32 * opacity / 255) as u8;
+        pixels[(y * size.width + x) as usize] = match a {
+            0 => Pixel::transparent(),
+            a => Pixel { a, ..*pixel },
+        };
+    }
+}
+
+fn read_layer(chunk: &mut Reader) -> Result<Layer> {
+    let flags = chunk.word()?;
+    let kind = chunk.word()?;
+    chunk.skip(2 + 2 + 2 + 2)?;
+    let opacity = chunk.byte()?;
+    chunk.skip(3)?;
+    let name = chunk.string()?;
+
+    Ok(Layer {
+        name,
+        image: kind == 0,
+        background: flags & 8 != 0,
+        opacity,
+    })
+}
+
+// Tags outside of the frames are refused, the rest of mixel counts on them being inside
+fn read_tags(chunk: &mut Reader, frame_count: usize) -> Result<Vec<Tag>> {
+    let count = chunk.word()?;
+    chunk.skip(8)?;
+
+    (0..count)
+        .map(|_| {
+            let from = chunk.word()? as usize;
+            let to = chunk.word()? as usize;
+            let direction = match chunk.byte()? {
+                1 => Direction::Reverse,
+                2 | 3 => Direction::PingPong,
+                _ => Direction::Forward,
+            };
+            chunk.skip(2 + 6 + 3 + 1)?;
+            let name = chunk.string()?;
+
+            if from > to || to >= frame_count {
+                bail!("tag {} is outside of the frames: {}-{}", name, from, to);
+            }
+
+            Ok(Tag {
+                name,
+                from,
+                to,
+                direction,
+            })
+        })
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+//     - Palette -
+//     Indexed pixels are a single byte, so the palette is never grown past
+//     the 256 entries it starts with and any entries after those are ignored.
+// -----------------------------------------------------------------------------
+fn read_palette(chunk: &mut Reader, palette: &mut [Pixel]) -> Result<()> {
+    chunk.dword()?;
+    let first = chunk.dword()? as usize;
+    let last = chunk.dword()? as usize;
+    chunk.skip(8)?;
+
+    for index in first..=last {
+        let flags = chunk.word()?;
+        let b = chunk.bytes(4)?;
+        let pixel = Pixel { r: b[0], g: b[1], b: b[2], a: b[3] };
+        if flags & 1 != 0 {
+            chunk.string()?;
+        }
+        if let Some(entry) = palette.get_mut(index) {
+            *entry = pixel;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_old_palette(chunk: &mut Reader, palette: &mut [Pixel]) -> Result<()> {
+    let packets = chunk.word()?;
+    let mut index = 0;
+
+    for _ in 0..packets {
+        index += chunk.byte()? as usize;
+        let count = match chunk.byte()? {
+            0 => 256,
+            n => n as usize,
+        };
+
+        for _ in 0..count {
+            let b = chunk.bytes(3)?;
+            if let Some(entry) = palette.get_mut(index) {
+                *entry = Pixel { r: b[0], g: b[1], b: b[2], a: 255 };
+            }
+            index += 1;
+        }
+    }
+
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - Writer -
+// -----------------------------------------------------------------------------
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    fn word(&mut self, v: u16) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn short(&mut self, v: i16) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn dword(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn zeros(&mut self, n: usize) {
+        self.data.extend(std::iter::repeat(0).take(n));
+    }
+
+    fn string(&mut self, s: &str) {
+        self.word(s.len() as u16);
+        self.data.extend_from_slice(s.as_bytes());
+    }
+
+    fn chunk(&mut self, kind: u16, chunk: Writer) {
+        self.dword(chunk.data.len() as u32 + 6);
+        self.word(kind);
+        self.data.extend(chunk.data);
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Write -
+//     Always 32 bit RGBA. The palette holds the colours in use (up to 256).
+//     Empty cels are left out, linked cels are only written once.
+// -----------------------------------------------------------------------------
+pub fn write(path: &Path, doc: &Document) -> Result<()> {
+    if doc.size.width > u16::MAX as i32 || doc.size.height > u16::MAX as i32 {
+        bail!("too large for an aseprite file");
+    }
+
+    let mut frames = Writer::default();
+
+    // The frame each group of linked cels was first written in
+    let mut written = HashMap::<(usize, usize), usize>::new();
+
+    for (index, frame) in doc.frames.iter().enumerate() {
+        let mut chunks = Vec::new();
+
+        if index == 0 {
+            chunks.push((PALETTE_CHUNK, palette_chunk(doc)));
+            for name in &doc.layer_names {
+                chunks.push((LAYER_CHUNK, layer_chunk(name)));
+            }
+            if !doc.tags.is_empty() {
+                chunks.push((TAGS_CHUNK, tags_chunk(&doc.tags)));
+            }
+        }
+
+        for (layer, pixels) in frame.layers.iter().enumerate() {
+            let link = frame.links[layer].map(|link| (layer, link));
+            if let Some(first) = link.and_then(|link| written.get(&link)) {
+                chunks.push((CEL_CHUNK, linked_cel_chunk(layer, *first)));
+                continue;
+            }
+
+            if pixels.iter().any(|p| p.a > 0) {
+                chunks.push((CEL_CHUNK, cel_chunk(layer, doc.size, pixels)?));
+                if let Some(link) = link {
+                    written.insert(link, index);
+                }
+            }
+        }
+
+        let mut body = Writer::default();
+        let count = chunks.len();
+        chunks.into_iter().for_each(|(kind, chunk)| body.chunk(kind, chunk));
+
+        frames.dword(body.data.len() as u32 + 16);
+        frames.word(FRAME_MAGIC);
+        frames.word(count.min(0xFFFF) as u16);
+        frames.word(frame.duration.min(u16::MAX as u32) as u16);
+        frames.zeros(2);
+        frames.dword(count as u32);
+        frames.data.extend(body.data);
+    }
+
+    let colors = palette(doc).len();
+    let mut header = Writer::default();
+    header.dword(frames.data.len() as u32 + 128);
+    header.word(HEADER_MAGIC);
+    header.word(doc.frames.len() as u16);
+    header.word(doc.size.width as u16);
+    header.word(doc.size.height as u16);
+    header.word(32);
+    // Layer opacity is valid
+    header.dword(1);
+    // Speed (deprecated)
+    header.word(100);
+    header.zeros(4 + 4);
+    // Transparent index
+    header.byte(0);
+    header.zeros(3);
+    header.word(match colors {
+        256 => 0,
+        n => n as u16,
+    });
+    // Pixel ratio
+    header.byte(1);
+    header.byte(1);
+    // Grid
+    header.short(0);
+    header.short(0);
+    header.word(16);
+    header.word(16);
+    header.zeros(84);
+
+    header.data.extend(frames.data);
+    fs::write(path, header.data)?;
+    Ok(())
+}
+
+fn palette(doc: &Document) -> Vec<Pixel> {
+    let mut colors = Vec::new();
+    let pixels = doc.frames.iter().flat_map(|f| f.layers.iter().flatten());
+
+    for pixel in pixels.filter(|p| p.a > 0) {
+        if colors.len() == 256 {
+            break;
+        }
+        if !colors.contains(pixel) {
+            colors.push(*pixel);
+        }
+    }
+
+    match colors.is_empty() {
+        true => vec![Pixel::black()],
+        false => colors,
+    }
+}
+
+fn palette_chunk(doc: &Document) -> Writer {
+    let colors = palette(doc);
+    let mut chunk = Writer::default();
+    chunk.dword(colors.len() as u32);
+    chunk.dword(0);
+    chunk.dword(colors.len() as u32 - 1);
+    chunk.zeros(8);
+
+    for color in colors {
+        chunk.word(0);
+        chunk.data.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+
+    chunk
+}
+
+fn layer_chunk(name: &str) -> Writer {
+    let mut chunk = Writer::default();
+    // Visible and editable
+    chunk.word(1 | 2);
+    // Image layer, no parent
+    chunk.word(0);
+    chunk.word(0);
+    chunk.zeros(2 + 2);
+    // Normal blend mode, fully opaque
+    chunk.word(0);
+    chunk.byte(255);
+    chunk.zeros(3);
+    chunk.string(name);
+    chunk
+}
+
+fn cel_chunk(layer: usize, size: Size<i32>, pixels: &[Pixel]) -> Result<Writer> {
+    let mut chunk = Writer::default();
+    chunk.word(layer as u16);
+    chunk.short(0);
+    chunk.short(0);
+    chunk.byte(255);
+    // Compressed image
+    chunk.word(2);
+    chunk.short(0);
+    chunk.zeros(5);
+    chunk.word(size.width as u16);
+    chunk.word(size.height as u16);
+
+    let bytes = pixels.iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect::<Vec<_>>();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&bytes)?;
+    chunk.data.extend(encoder.finish()?);
+
+    Ok(chunk)
+}
+
+fn linked_cel_chunk(layer: usize, frame: usize) -> Writer {
+    let mut chunk = Writer::default();
+    chunk.word(layer as u16);
+    chunk.short(0);
+    chunk.short(0);
+    chunk.byte(255);
+    // Linked cel
+    chunk.word(1);
+    chunk.short(0);
+    chunk.zeros(5);
+    chunk.word(frame as u16);
+    chunk
+}
+
+fn tags_chunk(tags: &[Tag]) -> Writer {
+    let mut chunk = Writer::default();
+    chunk.word(tags.len() as u16);
+    chunk.zeros(8);
+
+    for tag in tags {
+        chunk.word(tag.from as u16);
+        chunk.word(tag.to as u16);
+        chunk.byte(match tag.direction {
+            Direction::Forward => 0,
+            Direction::Reverse => 1,
+            Direction::PingPong => 2,
+        });
+        // Repeat forever
+        chunk.word(0);
+        chunk.zeros(6 + 3 + 1);
+        chunk.string(&tag.name);
+    }
+
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mixel-{}-{}.ase", name, std::process::id()))
+    }
+
+    fn document() -> Document {
+        let size = Size::new(3, 2);
+        let red = Pixel { r: 255, g: 0, b: 0, a: 255 };
+        let blue = Pixel { r: 0, g: 0, b: 255, a: 128 };
+        let t = Pixel::transparent();
+
+        let background = vec![red; 6];
+        let sprite = vec![t, blue, t, t, red, blue];
+
+        let frames = (0..3)
+            .map(|i| DocumentFrame {
+                layers: vec![background.clone(), sprite.clone()],
+                // The background is linked in every frame, the sprite only in the first two
+                links: vec![Some(1), if i < 2 { Some(2) } else { None }],
+                duration: 100 + i * 20,
+            })
+            .collect();
+
+        let tags = vec![Tag {
+            name: "walk".to_string(),
+            from: 1,
+            to: 2,
+            direction: Direction::PingPong,
+        }];
+
+        Document {
+            size,
+            layer_names: vec!["background".to_string(), "sprite".to_string()],
+            frames,
+            tags,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        let doc = document();
+        write(&path, &doc).unwrap();
+        let read = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((read.size.width, read.size.height), (3, 2));
+        assert_eq!(read.layer_names, doc.layer_names);
+        assert_eq!(read.frames.len(), doc.frames.len());
+
+        for (a, b) in read.frames.iter().zip(&doc.frames) {
+            assert_eq!(a.layers, b.layers);
+            assert_eq!(a.duration, b.duration);
+        }
+
+        // Links are renumbered, but the same cels are linked
+        let links = read.frames.iter().map(|f| f.links.clone()).collect::<Vec<_>>();
+        assert!(links[0][0].is_some());
+        assert!(links.iter().all(|l| l[0] == links[0][0]));
+        assert!(links[0][1].is_some());
+        assert_eq!(links[1][1], links[0][1]);
+        assert_ne!(links[0][0], links[0][1]);
+        assert_eq!(links[2][1], None);
+
+        assert_eq!(read.tags.len(), 1);
+        assert_eq!(read.tags[0].name, "walk");
+        assert_eq!((read.tags[0].from, read.tags[0].to), (1, 2));
+        assert_eq!(read.tags[0].direction, Direction::PingPong);
+    }
+
+    #[test]
+    fn palette_is_not_grown() {
+        let red = Pixel { r: 255, g: 0, b: 0, a: 255 };
+        let mut palette = vec![Pixel::transparent(); 256];
+
+        // Claims u32::MAX entries, sets entries 255 and 256
+        let mut data = Vec::new();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&255u32.to_le_bytes());
+        data.extend_from_slice(&256u32.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[0, 0, 255, 0, 0, 255]);
+        data.extend_from_slice(&[0, 0, 255, 0, 0, 255]);
+        read_palette(&mut Reader::new(&data), &mut palette).unwrap();
+
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[255], red);
+    }
+
+    #[test]
+    fn tags_outside_of_the_frames() {
+        let tag = |from: u16, to: u16| {
+            let mut data = Vec::new();
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&from.to_le_bytes());
+            data.extend_from_slice(&to.to_le_bytes());
+            data.extend_from_slice(&[0; 13]);
+            data.extend_from_slice(&4u16.to_le_bytes());
+            data.extend_from_slice(b"walk");
+            data
+        };
+
+        assert!(read_tags(&mut Reader::new(&tag(1, 2)), 3).is_ok());
+        assert!(read_tags(&mut Reader::new(&tag(1, 3)), 3).is_err());
+        assert!(read_tags(&mut Reader::new(&tag(2, 1)), 3).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let path = temp_path("malformed");
+        write(&path, &document()).unwrap();
+        let data = fs::read(&path).unwrap();
+
+        // Zero width
+        let mut zero = data.clone();
+        zero[8..10].copy_from_slice(&0u16.to_le_bytes());
+        fs::write(&path, &zero).unwrap();
+        assert!(read(&path).is_err());
+
+        // Too large to allocate
+        let mut large = data.clone();
+        large[8..10].copy_from_slice(&u16::MAX.to_le_bytes());
+        large[10..12].copy_from_slice(&u16::MAX.to_le_bytes());
+        fs::write(&path, &large).unwrap();
+        assert!(read(&path).is_err());
+
+        // Cut off half way
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        assert!(read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
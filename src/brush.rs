@@ -0,0 +1,145 @@
+use nightmaregl::{Pixel, Position, Size};
+
+pub const MAX_SIZE: i32 = 32;
+
+// -----------------------------------------------------------------------------
+//     - Brush -
+//     The offsets (relative to the cursor) painted by a single dab.
+//
+//     :brush square 4
+//     :brush round 3
+//     :brush custom .#./###/.#.
+//     :brush from-selection
+//
+//     A brush made from a selection is a stamp: it has a colour per offset.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Brush {
+    offsets: Vec<Position<i32>>,
+    colors: Option<Vec<Pixel>>,
+}
+
+impl Brush {
+    pub fn square(size: i32) -> Self {
+        Self::from_fn(size, size, |_, _| true)
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Round -
+    //     Compare doubled distances to stay in integers,
+    //     and shave the corners a bit more than a true circle would
+    //     as small round brushes look better that way.
+    // -----------------------------------------------------------------------------
+    pub fn round(size: i32) -> Self {
+        Self::from_fn(size, size, |x, y| {
+            let dx = 2 * x - (size - 1);
+            let dy = 2 * y - (size - 1);
+            dx * dx + dy * dy <= size * size - size
+        })
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Custom -
+    //     Rows are separated by `/`, and `#` marks a painted pixel.
+    // -----------------------------------------------------------------------------
+    pub fn custom(mask: &str) -> Option<Self> {
+        let rows = mask.split('/').map(str::as_bytes).collect::<Vec<_>>();
+        let height = rows.len() as i32;
+        let width = rows.iter().map(|r| r.len()).max()? as i32;
+
+        if width == 0 || width > MAX_SIZE || height > MAX_SIZE {
+            return None;
+        }
+
+        let brush = Self::from_fn(width, height, |x, y| {
+            rows[y as usize].get(x as usize) == Some(&b'#')
+        });
+
+        match brush.offsets.is_empty() {
+            true => None,
+            false => Some(brush),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Stamp -
+    //     `pixels` are in reading order. Fully transparent pixels
+    //     are left out of the stamp.
+    // -----------------------------------------------------------------------------
+    pub fn stamp(size: Size<i32>, pixels: &[Pixel]) -> Option<Self> {
+        if size.width > MAX_SIZE || size.height > MAX_SIZE {
+            return None;
+        }
+
+        let pixel = |x: i32, y: i32| pixels[(y * size.width + x) as usize];
+        let mut brush = Self::from_fn(size.width, size.height, |x, y| pixel(x, y).a > 0);
+
+        let center = Position::new((size.width - 1) / 2, (size.height - 1) / 2);
+        let colors = brush
+            .offsets
+            .iter()
+            .map(|o| *o + center)
+            .map(|p| pixel(p.x, p.y))
+            .collect();
+        brush.colors = Some(colors);
+
+        match brush.offsets.is_empty() {
+            true => None,
+            false => Some(brush),
+        }
+    }
+
+    fn from_fn(width: i32, height: i32, f: impl Fn(i32, i32) -> bool) -> Self {
+        let center = Position::new((width - 1) / 2, (height - 1) / 2);
+        let offsets = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|(x, y)| f(*x, *y))
+            .map(|(x, y)| Position::new(x, y) - center)
+            .collect();
+
+        Self {
+            offsets,
+            colors: None,
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Dab -
+    //     The positions and colours painted at `at`.
+    //     Recolouring keeps the alpha of the stamp but uses the colour of `fg`.
+    // -----------------------------------------------------------------------------
+    pub fn dab(&self, at: Position<i32>, fg: Pixel, recolor: bool) -> Vec<(Position<i32>, Pixel)> {
+        let positions = self.footprint(at).into_iter();
+
+        match &self.colors {
+            None => positions.map(|p| (p, fg)).collect(),
+            Some(colors) if recolor => positions
+                .zip(colors)
+                .map(|(p, c)| (p, Pixel { a: c.a, ..fg }))
+                .collect(),
+            Some(colors) => positions.zip(colors.iter().copied()).collect(),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Footprint -
+    //     The positions painted by a dab at `at`
+    // -----------------------------------------------------------------------------
+    pub fn footprint(&self, at: Position<i32>) -> Vec<Position<i32>> {
+        self.offsets.iter().map(|o| at + *o).collect()
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Stroke -
+    //     A dab at every position, without painting the same pixel twice
+    // -----------------------------------------------------------------------------
+    pub fn stroke(&self, positions: &[Position<i32>]) -> Vec<Position<i32>> {
+        let mut stroke = positions
+            .iter()
+            .flat_map(|p| self.footprint(*p))
+            .collect::<Vec<_>>();
+        stroke.sort_by_key(|p| (p.y, p.x));
+        stroke.dedup();
+        stroke
+    }
+}
@@ -1,14 +1,31 @@
 use std::path::Path;
+use std::time::Duration;
 
 use nightmaregl::texture::{Format, Texture};
 use nightmaregl::{
-    Color, Context, Framebuffer, Pixel, Pixels, Position, Renderer, Result, Size, Sprite,
+    Context, Framebuffer, Pixel, Pixels, Position, Renderer, Result, Size, Sprite,
     VertexData, Viewport,
 };
 
-use crate::commands::Command;
+use crate::aseprite::{self, Document, DocumentFrame};
+use crate::brush::Brush;
+use crate::color;
+use crate::dither;
+use crate::export::{self, Image};
+use crate::commands::{Command, FrameOp, Import, Range, Select, Substitute};
+use crate::fill::flood;
+use crate::floating::Floating;
+use crate::frame::{self, Direction, Frame, Tag};
+use crate::history::{Edit, History};
+use crate::import;
 use crate::input::{Action, InputHandler};
-use crate::Mode;
+use crate::layer::Layer;
+use crate::options::{parse_bool, Options, Sample};
+use crate::playback::Playback;
+use crate::register::Register;
+use crate::selection::{Mask, MaskOp, Selection};
+use crate::timelapse;
+use crate::{Insert, Mode};
 
 struct SaveBuffer {
     fb: Framebuffer,
@@ -44,20 +61,21 @@ impl SaveBuffer {
         self.texture = Texture::<i32>::new()
             .with_format(Format::Rgba)
             .with_no_data(size);
+        self.fb.attach_texture(&self.texture);
     }
 
     fn save(
         &self,
         sprite: &Sprite<i32>,
-        textures: &[Texture<i32>],
+        layers: &[Layer],
         viewport: &Viewport,
         path: impl AsRef<Path>,
         context: &mut Context,
     ) -> Result<()> {
         self.fb.bind();
-        textures.into_iter().for_each(|t| {
+        layers.into_iter().for_each(|l| {
             self.renderer
-                .render(t, &[self.sprite.vertex_data()], viewport, context);
+                .render(&l.texture, &[self.sprite.vertex_data()], viewport, context);
         });
 
         let res = self.texture.write_to_disk(path.as_ref());
@@ -85,37 +103,58 @@ impl Cursor {
     }
 }
 
+// The bottom layer of a new frame is filled with this
+const BACKGROUND: Pixel = Pixel {
+    r: 12,
+    g: 34,
+    b: 56,
+    a: 255,
+};
+
 pub struct Canvas {
-    layers: Vec<Texture<i32>>,
+    frames: Vec<Frame>,
+    frame: usize,
+    tags: Vec<Tag>,
+    next_link: usize,
+    layer_names: Vec<String>,
     layer: usize,
+    pending: Option<char>,
     renderer: Renderer<VertexData>,
     sprite: Sprite<i32>,
     cursor_sprite: Sprite<i32>,
     cursor_texture: Texture<i32>,
-    pix_buf: Pixels,
     viewport: Viewport,
     cursor: Cursor,
     mode: Mode,
     save_buffer: SaveBuffer,
+    selection: Option<Selection>,
+    mask: Option<Mask>,
+    history: History,
+    insert_edit: Edit,
+    // Changed since it was last opened or saved
+    modified: bool,
+    stroke: Vec<Position<i32>>,
+    pen_down: bool,
+    register: Option<Register>,
+    floating: Option<Floating>,
+    floating_layer: Layer,
+    onion_layer: Layer,
+    playback: Option<Playback>,
+    message: Option<String>,
+    search: Option<Pixel>,
+    highlights: Vec<Pixel>,
+    highlights_stale: bool,
+    marks_bounds: Option<(Position<i32>, Size<i32>)>,
+    options: Options,
+    anchor: Option<Position<i32>>,
 }
 
 impl Canvas {
     pub fn new(window_size: Size<i32>, size: Size<i32>, context: &mut Context) -> Result<Self> {
         let viewport = Viewport::new(Position::zero(), window_size);
 
-        let background_color: Pixel = Pixel {
-            r: 12,
-            g: 34,
-            b: 56,
-            a: 255,
-        };
-
-        // Main canvas texture
-        let texture = {
-            eprintln!("{:?}", background_color);
-            let pixels = Pixels::from_pixel(background_color.into(), size.cast());
-            Texture::default_with_data(size, pixels.as_bytes())
-        };
+        // Main canvas layer
+        let layer = Layer::new(size, BACKGROUND);
 
         // Cursor texture
         let cursor_texture = {
@@ -126,7 +165,7 @@ impl Canvas {
         let mut renderer = Renderer::<VertexData>::default(context)?;
         renderer.pixel_size = 16;
 
-        let mut sprite = Sprite::new(texture.size());
+        let mut sprite = Sprite::new(layer.size());
         sprite.z_index = 10;
         sprite.position = window_size.to_vector() / 2 / renderer.pixel_size as i32 - sprite.size.to_vector() / 2;
 
@@ -136,17 +175,40 @@ impl Canvas {
         let save_buffer = SaveBuffer::new(size, context)?;
 
         let mut inst = Self {
-            layers: vec![texture],
+            frames: vec![Frame::new(vec![layer])],
+            frame: 0,
+            tags: Vec::new(),
+            next_link: 0,
+            layer_names: vec!["background".to_string()],
             layer: 0,
+            pending: None,
             cursor_texture,
             sprite,
             cursor_sprite,
-            pix_buf: Pixels::from_size(Size::new(1, 1)),
             viewport,
             renderer,
             cursor: Cursor::new(),
             mode: Mode::Normal,
             save_buffer,
+            selection: None,
+            mask: None,
+            history: History::new(),
+            insert_edit: Edit::new(),
+            modified: false,
+            stroke: Vec::new(),
+            pen_down: true,
+            register: None,
+            floating: None,
+            floating_layer: Layer::new(size, Pixel::transparent()),
+            onion_layer: Layer::new(size, Pixel::transparent()),
+            playback: None,
+            message: None,
+            search: None,
+            highlights: Vec::new(),
+            highlights_stale: true,
+            marks_bounds: None,
+            options: Options::new(),
+            anchor: None,
         };
 
         // Position the cursor or it won't be
@@ -162,16 +224,48 @@ impl Canvas {
     pub fn render(&mut self, context: &mut Context) {
         let vertex_data = [self.sprite.vertex_data()];
 
-        self.layers.iter().for_each(|t| {
+        // While playing the frame being played is shown instead
+        let frame = match self.playback {
+            Some(playback) => playback.frame,
+            None => self.frame,
+        };
+
+        self.frames[frame].layers.iter().for_each(|l| {
             let res = self
                 .renderer
-                .render(t, &vertex_data, &self.viewport, context);
+                .render(&l.texture, &vertex_data, &self.viewport, context);
 
             if let Err(e) = res {
                 eprintln!("canvas render: {:?}", e);
             }
         });
 
+        if self.options.onion > 0 && self.playback.is_none() {
+            let res = self.renderer.render(
+                &self.onion_layer.texture,
+                &vertex_data,
+                &self.viewport,
+                context,
+            );
+
+            if let Err(e) = res {
+                eprintln!("onion render: {:?}", e);
+            }
+        }
+
+        if self.floating.is_some() {
+            let res = self.renderer.render(
+                &self.floating_layer.texture,
+                &vertex_data,
+                &self.viewport,
+                context,
+            );
+
+            if let Err(e) = res {
+                eprintln!("floating render: {:?}", e);
+            }
+        }
+
         let res = self.renderer.render(
             &self.cursor_texture,
             &[self.cursor_sprite.vertex_data()],
@@ -184,88 +278,1891 @@ impl Canvas {
         }
     }
 
+    // -----------------------------------------------------------------------------
+    //     - Layers -
+    //     The layer stack of the current frame
+    // -----------------------------------------------------------------------------
+    fn layers(&self) -> &Vec<Layer> {
+        &self.frames[self.frame].layers
+    }
+
+    fn layers_mut(&mut self) -> &mut Vec<Layer> {
+        &mut self.frames[self.frame].layers
+    }
+
+    fn layer_mut(&mut self) -> &mut Layer {
+        let index = self.layer;
+        &mut self.layers_mut()[index]
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Frames -
+    //     New and duplicated frames go after the current one
+    //     and become the current frame.
+    // -----------------------------------------------------------------------------
+    fn frame_op(&mut self, op: FrameOp) {
+        self.commit_floating();
+        self.playback = None;
+
+        if !matches!(op, FrameOp::Goto(_)) {
+            self.modified = true;
+        }
+
+        match op {
+            FrameOp::New => {
+                // Every layer starts out like the same layer in this frame did
+                let layers = self
+                    .layers()
+                    .iter()
+                    .map(|layer| Layer::new(layer.size(), layer.blank()))
+                    .collect();
+                self.insert_frame(Frame::new(layers));
+            }
+            FrameOp::Duplicate => {
+                let frame = self.frames[self.frame].duplicate();
+                self.insert_frame(frame);
+            }
+            FrameOp::Delete => {
+                if self.frames.len() == 1 {
+                    self.message = Some("frame: can't delete the only frame".to_string());
+                    return;
+                }
+
+                let removed = self.frame;
+                self.frames.remove(removed);
+                frame::frame_removed(&mut self.tags, removed);
+                self.history.remap_frames(|f| match f {
+                    f if f == removed => None,
+                    f if f > removed => Some(f - 1),
+                    f => Some(f),
+                });
+                self.goto_frame(removed.min(self.frames.len() - 1));
+            }
+            FrameOp::Goto(n) => match n {
+                n if n >= 1 && n <= self.frames.len() => self.goto_frame(n - 1),
+                _ => self.message = Some(format!("frame: no frame {}", n)),
+            },
+            FrameOp::Duration(ms) => self.frames[self.frame].duration = ms,
+        }
+    }
+
+    fn insert_frame(&mut self, frame: Frame) {
+        let at = self.frame + 1;
+        self.frames.insert(at, frame);
+        frame::frame_inserted(&mut self.tags, at);
+        self.history.remap_frames(|f| match f >= at {
+            true => Some(f + 1),
+            false => Some(f),
+        });
+        self.goto_frame(at);
+    }
+
+    fn goto_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        self.selection = None;
+        self.layer_changed();
+        self.draw_onion();
+        self.draw_overlay();
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Onion skin -
+    //     The previous and next `onion` frames, tinted and fading out
+    //     the further away they are. Only shown where nothing is painted
+    //     on the current frame, so it looks like it is behind it.
+    //     The onion layer is never part of a frame so it is never saved.
+    // -----------------------------------------------------------------------------
+    fn draw_onion(&mut self) {
+        self.onion_layer.fill(Pixel::transparent());
+
+        let depth = self.options.onion;
+        if depth == 0 {
+            return self.onion_layer.sync();
+        }
+
+        // Furthest first so the closer frames end up on top
+        let frames = (1..=depth).rev().flat_map(|distance| {
+            let prev = self.frame.checked_sub(distance);
+            let next = Some(self.frame + distance).filter(|f| *f < self.frames.len());
+            vec![
+                prev.map(|f| (f, distance, self.options.onion_prev)),
+                next.map(|f| (f, distance, self.options.onion_next)),
+            ]
+        });
+
+        let positions = self.layers()[0].positions().collect::<Vec<_>>();
+        let mut onion = vec![Pixel::transparent(); positions.len()];
+
+        for (frame, distance, tint) in frames.flatten() {
+            let tint = Pixel {
+                a: (tint.a as usize / distance) as u8,
+                ..tint
+            };
+
+            for (i, pos) in positions.iter().enumerate() {
+                if self.painted(frame, *pos) {
+                    onion[i] = color::blend(onion[i], tint);
+                }
+            }
+        }
+
+        for (i, pos) in positions.into_iter().enumerate() {
+            if !self.painted(self.frame, pos) {
+                self.onion_layer.set_pixel(pos, onion[i]);
+            }
+        }
+
+        self.onion_layer.sync();
+    }
+
+    // Painted on any layer of the frame
+    fn painted(&self, frame: usize, pos: Position<i32>) -> bool {
+        self.frames[frame].layers.iter().any(|l| l.painted(pos))
+    }
+
+    // Wraps around at either end
+    fn step_frame(&mut self, step: i32) {
+        self.commit_floating();
+        let frame = (self.frame as i32 + step).rem_euclid(self.frames.len() as i32);
+        self.goto_frame(frame as usize);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Playback -
+    //     Plays a tag, otherwise the loop range, or every frame if there isn't one
+    // -----------------------------------------------------------------------------
+    fn play(&mut self, tag: Option<String>) {
+        let count = self.frames.len();
+        let direction = match self.options.ping_pong {
+            true => Direction::PingPong,
+            false => Direction::Forward,
+        };
+
+        let (first, last, direction) = match (tag, self.options.loop_range) {
+            (Some(name), _) => match self.tags.iter().find(|t| t.name == name) {
+                Some(tag) => (tag.from, tag.to, tag.direction),
+                None => {
+                    self.message = Some(format!("play: no tag {}", name));
+                    return;
+                }
+            },
+            (None, Some((first, last))) => (first - 1, last - 1, direction),
+            (None, None) => (0, count - 1, direction),
+        };
+
+        if last >= count {
+            let message = format!("play: no frames {}-{} (there are {})", first + 1, last + 1, count);
+            self.message = Some(message);
+            return;
+        }
+
+        self.playback = Some(Playback::new(first, last, direction));
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        let durations = self.frames.iter().map(|f| f.duration).collect::<Vec<_>>();
+        if let Some(playback) = self.playback.as_mut() {
+            playback.advance(dt, &durations);
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Tags -
+    //     A new tag replaces one with the same name
+    // -----------------------------------------------------------------------------
+    fn tag(&mut self, tag: Tag) {
+        if tag.to >= self.frames.len() {
+            self.message = Some(format!("tag: there are only {} frames", self.frames.len()));
+            return;
+        }
+
+        self.tags.retain(|t| t.name != tag.name);
+        self.tags.push(tag);
+        self.tags.sort_by_key(|t| t.from);
+        self.modified = true;
+    }
+
+    fn untag(&mut self, name: &str) {
+        let count = self.tags.len();
+        self.tags.retain(|t| t.name != name);
+        if self.tags.len() != count {
+            self.modified = true;
+        }
+    }
+
+    fn list_tags(&mut self) {
+        let tags = self
+            .tags
+            .iter()
+            .map(|t| format!("{} {}-{} {}", t.name, t.from + 1, t.to + 1, t.direction.as_str()))
+            .collect::<Vec<_>>();
+
+        self.message = match tags.is_empty() {
+            true => Some("no tags".to_string()),
+            false => Some(tags.join(" | ")),
+        };
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Message -
+    //     Shown in place of the mode indicator until the next key press
+    // -----------------------------------------------------------------------------
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message = None;
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Frame label -
+    //     Shown in the mode indicator once there is more than one frame
+    // -----------------------------------------------------------------------------
+    pub fn frame_label(&self) -> Option<String> {
+        if let Some(playback) = self.playback {
+            return Some(format!("playing {}/{}", playback.frame + 1, self.frames.len()));
+        }
+
+        let linked = match self.layers()[self.layer].link {
+            Some(_) => " linked",
+            None => "",
+        };
+
+        match self.frames.len() {
+            1 => None,
+            count => Some(format!(
+                "frame {}/{} ({}ms){}",
+                self.frame + 1,
+                count,
+                self.frames[self.frame].duration,
+                linked
+            )),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Show edited frame -
+    //     After an undo / redo, sync the layers that were changed
+    //     and go to the frame that was edited
+    // -----------------------------------------------------------------------------
+    fn show_edited_frame(&mut self, frame: usize, mut edited: Vec<(usize, usize)>) {
+        edited.sort();
+        edited.dedup();
+        for (frame, layer) in edited {
+            self.frames[frame].layers[layer].sync();
+        }
+
+        self.goto_frame(frame);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Linked cels -
+    //     Repeat the changes made to a linked cel on every cel linked to it,
+    //     as part of the same edit so they are undone together.
+    // -----------------------------------------------------------------------------
+    fn edit_linked(&mut self, edit: &mut Edit) {
+        let mut linked = Vec::new();
+
+        for change in &edit.changes {
+            let link = match self.frames[change.frame].layers[change.layer].link {
+                Some(link) => link,
+                None => continue,
+            };
+
+            for (frame, other) in self.frames.iter_mut().enumerate() {
+                let cel = &mut other.layers[change.layer];
+                if frame == change.frame || cel.link != Some(link) {
+                    continue;
+                }
+
+                if let Some(before) = cel.set_pixel(change.position, change.after) {
+                    linked.push((frame, change.layer, change.position, before, change.after));
+                }
+            }
+        }
+
+        let mut synced = Vec::new();
+        for (frame, layer, position, before, after) in linked {
+            edit.push((frame, layer), position, before, after);
+            if !synced.contains(&(frame, layer)) {
+                synced.push((frame, layer));
+            }
+        }
+
+        for (frame, layer) in synced {
+            self.frames[frame].layers[layer].sync();
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Link / Unlink -
+    //     Link the cels of the current layer in a range of frames (counting from 1).
+    //     They all get the pixels of the current frame, or of the first frame
+    //     in the range if the current frame is not in it.
+    //     Linking and unlinking are undone like any other edit.
+    // -----------------------------------------------------------------------------
+    fn link(&mut self, first: usize, last: usize) {
+        self.commit_floating();
+
+        if last > self.frames.len() {
+            self.message = Some(format!("link: there are only {} frames", self.frames.len()));
+            return;
+        }
+
+        let (first, last) = (first - 1, last - 1);
+        let source = match (first..=last).contains(&self.frame) {
+            true => self.frame,
+            false => first,
+        };
+
+        let layer = self.layer;
+        let link = match self.frames[source].layers[layer].link {
+            Some(link) => link,
+            None => {
+                self.next_link += 1;
+                self.next_link
+            }
+        };
+
+        let pixels = self.frames[source].layers[layer].pixels().to_vec();
+        let mut edit = Edit::new();
+
+        for frame in first..=last {
+            let cel = &mut self.frames[frame].layers[layer];
+            edit.link((frame, layer), cel.link, Some(link));
+            cel.link = Some(link);
+            if frame == source {
+                continue;
+            }
+
+            let positions = cel.positions().collect::<Vec<_>>();
+            for (pos, after) in positions.into_iter().zip(&pixels) {
+                if let Some(before) = cel.set_pixel(pos, *after) {
+                    edit.push((frame, layer), pos, before, *after);
+                }
+            }
+            cel.sync();
+        }
+
+        self.push_edit(edit);
+    }
+
+    fn unlink(&mut self) {
+        let (frame, layer) = (self.frame, self.layer);
+        let mut edit = Edit::new();
+        edit.link((frame, layer), self.layer_mut().link.take(), None);
+        self.push_edit(edit);
+    }
+
     // -----------------------------------------------------------------------------
     //     - Move cursor -
     // -----------------------------------------------------------------------------
     pub fn move_cursor(&mut self, move_by: Position<i32>) {
-        // Put the old pixel back
-        let draw_at = self.cursor.position;
-        self.pix_buf.push(Pixel::transparent());
-        self.cursor_texture
-            .write_region(draw_at, Size::new(1, 1), self.pix_buf.as_bytes());
-        self.pix_buf.clear();
-
-        // Move the cursor
         self.cursor.position += move_by;
 
-        // Render cursor
-        let draw_at = self.cursor.position;
-        let pixel = self.cursor.color;
-        self.pix_buf.push(pixel);
-        self.cursor_texture.write_region(
-            draw_at,
-            Size::new(1, 1),
-            self.pix_buf.as_bytes()
-        );
-        self.pix_buf.clear();
+        if let Some(selection) = self.selection.as_mut() {
+            selection.cursor = self.cursor.position;
+        }
+
+        // Floating pixels follow the cursor
+        if let Some(floating) = self.floating.as_mut() {
+            floating.position += move_by;
+            self.draw_floating();
+        }
+
+        self.draw_overlay();
     }
 
     // -----------------------------------------------------------------------------
-    //     - Draw cursor -
+    //     - Floating -
+    //     Lifted or pasted pixels live on their own layer until committed.
+    //     Lifting is not an edit on its own: the lifted pixels are put back
+    //     when cancelling, and lifting plus committing is undone in one go.
     // -----------------------------------------------------------------------------
-    pub fn draw(&mut self) {
-        // Put a new pixel in place
-        let draw_at = self.cursor.position;
-        let pixel = Color::white().into();
-        self.pix_buf.push(pixel);
-        let texture = &self.layers[self.layer];
-        texture.write_region(draw_at, Size::new(1, 1), self.pix_buf.as_bytes());
-        self.pix_buf.clear();
+    fn draw_floating(&mut self) {
+        self.floating_layer.fill(Pixel::transparent());
+
+        if let Some(floating) = &self.floating {
+            for (pos, pixel) in floating.pixels() {
+                if self.floating_layer.contains(pos) {
+                    self.floating_layer.set_pixel(pos, pixel);
+                }
+            }
+        }
+
+        self.floating_layer.sync();
+    }
+
+    fn float(&mut self, floating: Floating) {
+        self.floating = Some(floating);
+        self.draw_floating();
+    }
+
+    fn commit_floating(&mut self) {
+        let floating = match self.floating.take() {
+            Some(floating) => floating,
+            None => return,
+        };
+
+        self.draw_floating();
+        self.restore(&floating.lifted);
+
+        let layer = &self.layers()[self.layer];
+        let lifted = floating.lifted.iter().map(|(pos, _)| *pos).collect::<Vec<_>>();
+        let hole = Mask::from_positions(layer.size(), &lifted);
+
+        let mut pixels = lifted
+            .into_iter()
+            .map(|pos| (pos, Pixel::transparent()))
+            .collect::<Vec<_>>();
+
+        for (pos, src) in floating.pixels() {
+            let before = match hole.contains(pos) {
+                true => Pixel::transparent(),
+                false => match layer.pixel(pos) {
+                    Some(before) => before,
+                    None => continue,
+                },
+            };
+
+            // The alpha lock only protects what was not lifted
+            let locked = layer.alpha_lock && !hole.contains(pos);
+            let after = match locked {
+                true if before.a == 0 => continue,
+                true => Pixel {
+                    a: before.a,
+                    ..color::blend(before, src)
+                },
+                false => color::blend(before, src),
+            };
+            pixels.push((pos, after));
+        }
+
+        self.put(pixels);
+    }
+
+    fn cancel_floating(&mut self) {
+        if let Some(floating) = self.floating.take() {
+            self.restore(&floating.lifted);
+            self.draw_floating();
+            self.draw_onion();
+        }
+    }
+
+    // Put back the pixels that were lifted off the current layer
+    fn restore(&mut self, lifted: &[(Position<i32>, Pixel)]) {
+        if lifted.is_empty() {
+            return;
+        }
+
+        let layer = self.layer_mut();
+        for (pos, pixel) in lifted {
+            layer.set_pixel(*pos, *pixel);
+        }
+        layer.sync();
+        self.layer_changed();
     }
 
     // -----------------------------------------------------------------------------
-    //     - Input handling -
+    //     - Transform -
+    //     Rotate (clockwise) or scale the floating pixels.
+    //     If nothing is floating the selection, or the whole layer,
+    //     is lifted off the layer first.
+    //     Quarter turns are exact, any other angle uses RotSprite.
     // -----------------------------------------------------------------------------
-    pub fn input(&mut self, c: char, mode: Mode, input: &InputHandler) {
-        match mode {
-            Mode::Command => return,
-            Mode::Normal | Mode::Visual | Mode::Insert => {
-                let action = input.to_action(c, mode);
-                match action {
-                    Some(Action::Left) => self.move_cursor(Position::new(-1, 0)),
-                    Some(Action::Right) => self.move_cursor(Position::new(1, 0)),
-                    Some(Action::Up) => self.move_cursor(Position::new(0, -1)),
-                    Some(Action::Down) => self.move_cursor(Position::new(0, 1)),
-                    _ => {}
+    fn rotate(&mut self, degrees: i32) {
+        if self.floating.is_none() {
+            self.lift_selected();
+        }
+
+        if let Some(floating) = self.floating.as_mut() {
+            match degrees % 90 {
+                0 => {
+                    let quarters = (degrees / 90).rem_euclid(4);
+                    (0..quarters).for_each(|_| floating.rotate(true));
+                }
+                _ => {
+                    if !floating.rotate_by(degrees as f32) {
+                        self.message = Some("rotate: the selection is too large".to_string());
+                    }
                 }
             }
+            self.draw_floating();
+        }
+    }
+
+    fn scale(&mut self, factor: i32) {
+        if self.floating.is_none() {
+            self.lift_selected();
         }
 
-        if let Mode::Insert = mode {
-            self.draw();
+        if let Some(floating) = self.floating.as_mut() {
+            if !floating.scale(factor) {
+                self.message = Some("scale: the result would be too large".to_string());
+            }
+            self.draw_floating();
         }
     }
 
     // -----------------------------------------------------------------------------
-    //     - TODO -
-    //     * On save use the framebuffer rendere for all renders
-    //     * Set the framebuffer texture to the same size as the textures to save
-    //     * Set the framebuffer sprite to the same size as the textures to save
+    //     - Draw overlay -
+    //     Redraw the cursor texture: search matches and the selection mask
+    //     (the highlights), the visual selection, a preview of the current shape
+    //     and the cursor itself (the marks).
+    //     The highlights are only found again once they are stale, otherwise
+    //     only the area around the old and the new marks is uploaded.
     // -----------------------------------------------------------------------------
-    pub fn exec(&mut self, command: Command, context: &mut Context) {
-        match command {
-            Command::Write(path) => {
-                let res = self.save_buffer.save(
-                    &self.sprite,
-                    &self.layers,
-                    &self.viewport,
-                    path,
-                    context,
-                );
+    fn draw_overlay(&mut self) {
+        let size = self.cursor_texture.size();
+        let full = self.highlights_stale;
+        if full {
+            self.draw_highlights();
+        }
 
-                eprintln!("{:?}", res);
+        let marks = self.marks();
+        let bounds = bounds(marks.iter().map(|(pos, _)| *pos), size);
+        let region = match full {
+            true => Some((Position::zero(), size)),
+            false => union(bounds, self.marks_bounds),
+        };
+        self.marks_bounds = bounds;
+
+        let (origin, region_size) = match region {
+            Some(region) => region,
+            None => return,
+        };
+
+        let mut pixels = (0..region_size.height)
+            .flat_map(|y| (0..region_size.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.highlights[((origin.y + y) * size.width + origin.x + x) as usize])
+            .collect::<Vec<_>>();
+
+        for (pos, pixel) in marks {
+            let offset = pos - origin;
+            if offset.x >= 0 && offset.y >= 0 && offset.x < region_size.width && offset.y < region_size.height {
+                pixels[(offset.y * region_size.width + offset.x) as usize] = pixel;
             }
-            _ => {}
         }
+
+        let mut bytes = Vec::with_capacity(pixels.len() * 4);
+        pixels.iter().for_each(|p| bytes.extend_from_slice(&[p.r, p.g, p.b, p.a]));
+        self.cursor_texture.write_region(origin, region_size, &bytes);
+    }
+
+    fn draw_highlights(&mut self) {
+        let size = self.cursor_texture.size();
+        let mut highlights = vec![Pixel::transparent(); (size.width * size.height) as usize];
+        let mut set_pixel = |pos: Position<i32>, pixel: Pixel| {
+            if pos.x >= 0 && pos.y >= 0 && pos.x < size.width && pos.y < size.height {
+                highlights[(pos.y * size.width + pos.x) as usize] = pixel;
+            }
+        };
+
+        if let Some(color) = self.search {
+            let highlight = Pixel {
+                r: 255,
+                g: 255,
+                b: 0,
+                a: 128,
+            };
+            let layer = &self.layers()[self.layer];
+            layer
+                .positions()
+                .filter(|pos| layer.pixel(*pos) == Some(color))
+                .for_each(|pos| {
+                    set_pixel(pos, highlight);
+                });
+        }
+
+        if let Some(mask) = &self.mask {
+            let pixel = Pixel {
+                r: 0,
+                g: 160,
+                b: 255,
+                a: 96,
+            };
+            mask.positions().into_iter().for_each(|pos| {
+                set_pixel(pos, pixel);
+            });
+        }
+
+        self.highlights = highlights;
+        self.highlights_stale = false;
+    }
+
+    // In the order they are drawn
+    fn marks(&self) -> Vec<(Position<i32>, Pixel)> {
+        let mut marks = Vec::new();
+
+        if let Some(selection) = self.selection {
+            let pixel = Pixel {
+                a: 96,
+                ..self.cursor.color
+            };
+            marks.extend(selection.positions().into_iter().map(|pos| (pos, pixel)));
+        }
+
+        if let Some(anchor) = self.anchor {
+            let shape = self.options.shape.positions(anchor, self.cursor.position);
+            let stroke = self.options.brush.stroke(&shape);
+            let stroke = self.mirrored(&stroke);
+            marks.extend(stroke.into_iter().map(|pos| (pos, self.options.fg)));
+        }
+
+        // The brush footprint, with the actual cursor position drawn solid
+        let footprint = Pixel {
+            a: 160,
+            ..self.cursor.color
+        };
+        let brush = self.options.brush.footprint(self.cursor.position);
+        let brush = self.mirrored(&brush);
+        marks.extend(brush.into_iter().map(|pos| (pos, footprint)));
+
+        marks.push((self.cursor.position, self.cursor.color));
+        marks
+    }
+
+    // The layer changed, so the search matches have to be found again
+    fn layer_changed(&mut self) {
+        if self.search.is_some() {
+            self.highlights_stale = true;
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Search -
+    //     Jump to the next pixel of the search colour in reading order,
+    //     wrapping around at the end of the layer.
+    // -----------------------------------------------------------------------------
+    fn search(&mut self, color: Pixel) {
+        self.search = Some(color);
+        self.highlights_stale = true;
+        self.jump_to_match(true);
+    }
+
+    fn jump_to_match(&mut self, forward: bool) {
+        let color = match self.search {
+            Some(color) => color,
+            None => return,
+        };
+
+        let layer = &self.layers()[self.layer];
+        let width = layer.size().width;
+        let count = layer.size().width * layer.size().height;
+        let cursor = self.cursor.position;
+        let start = (cursor.y * width + cursor.x).max(0).min(count - 1);
+
+        let found = (1..=count)
+            .map(|step| match forward {
+                true => (start + step) % count,
+                false => (start + count - step) % count,
+            })
+            .map(|i| Position::new(i % width, i / width))
+            .find(|pos| layer.pixel(*pos) == Some(color));
+
+        if let Some(pos) = found {
+            self.move_cursor(pos - cursor);
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Draw cursor -
+    // -----------------------------------------------------------------------------
+    pub fn draw(&mut self, insert: Insert) {
+        // Put new pixels in place
+        let brush = &self.options.brush;
+        let mirror = self.options.mirror;
+        let index = self.layer;
+        let size = self.layers()[index].size();
+        let pattern = &self.options.pattern;
+
+        let mut dab = brush
+            .dab(self.cursor.position, self.options.fg, self.options.recolor)
+            .into_iter()
+            .flat_map(|(pos, pixel)| {
+                let mirrored = mirror.positions(pos, size).into_iter();
+                mirrored.map(move |pos| (pos, pixel))
+            })
+            .filter(|(pos, _)| pattern.paints(*pos))
+            .collect::<Vec<_>>();
+
+        // A pixel on the mirror axis is mirrored onto itself
+        dab.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        dab.dedup_by_key(|(pos, _)| *pos);
+
+        // Blend with the pixel from before the stroke, otherwise
+        // going back and forth would keep building up the opacity.
+        let changes = dab
+            .into_iter()
+            .filter_map(|(draw_at, pixel)| {
+                let current = self.layers()[index].pixel(draw_at)?;
+                let before = self.insert_edit.before((self.frame, index), draw_at).unwrap_or(current);
+                let after = match insert {
+                    Insert::Erase => self.erase(before)?,
+                    _ => self.apply(before, pixel)?,
+                };
+                Some((draw_at, current, after))
+            })
+            .collect::<Vec<_>>();
+
+        for (draw_at, current, after) in changes {
+            self.layers_mut()[index].write_pixel(draw_at, after);
+
+            // Painted pixels are in front of the onion skin
+            if self.options.onion > 0 && self.layers()[index].painted(draw_at) {
+                self.onion_layer.write_pixel(draw_at, Pixel::transparent());
+            }
+
+            self.insert_edit.push((self.frame, index), draw_at, current, after);
+        }
+        self.layer_changed();
+
+        // Pixel perfect only makes sense for single pixel brushes
+        let single = self.options.brush.footprint(self.cursor.position).len() == 1;
+        if self.options.pixel_perfect && single {
+            if self.stroke.last() != Some(&self.cursor.position) {
+                self.stroke.push(self.cursor.position);
+            }
+            self.pixel_perfect();
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Pixel perfect -
+    //     Remove the corner of an L (three pixels where the first and the last
+    //     are diagonal neighbours) from the stroke, by putting back
+    //     what was there before the stroke.
+    // -----------------------------------------------------------------------------
+    fn pixel_perfect(&mut self) {
+        let n = self.stroke.len();
+        if n < 3 {
+            return;
+        }
+
+        let (a, b, c) = (self.stroke[n - 3], self.stroke[n - 2], self.stroke[n - 1]);
+        let corner = (a.x == b.x || a.y == b.y)
+            && (b.x == c.x || b.y == c.y)
+            && (a.x - c.x).abs() == 1
+            && (a.y - c.y).abs() == 1;
+
+        if !corner {
+            return;
+        }
+
+        self.stroke.remove(n - 2);
+
+        let index = self.layer;
+        let size = self.layers()[index].size();
+        let mirror = self.options.mirror;
+        let kept = self
+            .stroke
+            .iter()
+            .flat_map(|p| mirror.positions(*p, size))
+            .collect::<Vec<_>>();
+
+        for pos in mirror.positions(b, size) {
+            if kept.contains(&pos) {
+                continue;
+            }
+
+            let before = match self.insert_edit.before((self.frame, index), pos) {
+                Some(before) => before,
+                None => continue,
+            };
+
+            if let Some(current) = self.layers_mut()[index].write_pixel(pos, before) {
+                self.insert_edit.push((self.frame, index), pos, current, before);
+            }
+        }
+    }
+
+    fn end_insert(&mut self) {
+        let mut edit = std::mem::take(&mut self.insert_edit);
+        self.edit_linked(&mut edit);
+        self.push_edit(edit);
+        self.stroke.clear();
+        self.draw_onion();
+    }
+
+    pub fn pen_down(&self) -> bool {
+        self.pen_down
+    }
+
+    pub fn selecting(&self) -> bool {
+        self.selection.is_some()
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Apply -
+    //     What a painted pixel turns into on the current layer:
+    //     brush opacity, alpha compositing and the alpha lock.
+    //     `None` if the pixel is left alone.
+    // -----------------------------------------------------------------------------
+    fn apply(&self, before: Pixel, src: Pixel) -> Option<Pixel> {
+        let alpha_lock = self.layers()[self.layer].alpha_lock;
+        if alpha_lock && before.a == 0 {
+            return None;
+        }
+
+        let src = Pixel {
+            a: (src.a as u32 * self.options.opacity as u32 / 100) as u8,
+            ..src
+        };
+
+        let mut pixel = color::blend(before, src);
+        if alpha_lock {
+            pixel.a = before.a;
+        }
+
+        Some(pixel)
+    }
+
+    fn erase(&self, before: Pixel) -> Option<Pixel> {
+        if self.layers()[self.layer].alpha_lock {
+            return None;
+        }
+
+        let a = before.a as u32 * (100 - self.options.opacity as u32) / 100;
+        match a {
+            0 => Some(Pixel::transparent()),
+            a => Some(Pixel { a: a as u8, ..before }),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Brush from selection -
+    //     Turn the selected pixels of the current layer into a stamp
+    // -----------------------------------------------------------------------------
+    fn brush_from_selection(&mut self) {
+        self.commit_floating();
+
+        let (_, register) = match self.selected_pixels() {
+            Some(selected) => selected,
+            None => return,
+        };
+
+        match Brush::stamp(register.size, &register.pixels) {
+            Some(brush) => self.options.brush = brush,
+            None => self.message = Some("brush: selection is empty or too large".to_string()),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Selected -
+    //     The visual selection if there is one, otherwise the selection mask.
+    //     Operators (fill, yank, delete, flip, substitute...) work on this.
+    // -----------------------------------------------------------------------------
+    fn selected(&self) -> Option<Mask> {
+        match self.selection {
+            Some(selection) => Some(selection.mask()),
+            None => self.mask.clone(),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Selected pixels -
+    //     The origin of the rectangle around the selection, and the pixels of the
+    //     current layer inside of it.
+    //     Unselected pixels are transparent.
+    // -----------------------------------------------------------------------------
+    fn selected_pixels(&self) -> Option<(Position<i32>, Register)> {
+        self.pixels_in(&self.selected()?)
+    }
+
+    fn pixels_in(&self, mask: &Mask) -> Option<(Position<i32>, Register)> {
+        let (origin, size) = mask.bounds()?;
+        let layer = &self.layers()[self.layer];
+
+        let pixels = (0..size.height)
+            .flat_map(|y| (0..size.width).map(move |x| origin + Position::new(x, y)))
+            .map(|pos| match mask.contains(pos) {
+                true => layer.pixel(pos).unwrap_or(Pixel::transparent()),
+                false => Pixel::transparent(),
+            })
+            .collect();
+
+        Some((origin, Register { size, pixels }))
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Select -
+    //     Change the selection mask
+    // -----------------------------------------------------------------------------
+    fn select(&mut self, select: Select, op: MaskOp) {
+        self.commit_floating();
+
+        let size = self.layers()[self.layer].size();
+        let tolerance = self.options.tolerance;
+
+        let mask = match select {
+            Select::Wand => {
+                let target = match self.sample(self.cursor.position) {
+                    Some(target) => target,
+                    None => return,
+                };
+
+                let region = flood(
+                    self.cursor.position,
+                    size,
+                    self.options.connectivity,
+                    |pos| match self.sample(pos) {
+                        Some(p) => color::matches(p, target, tolerance),
+                        None => false,
+                    },
+                );
+
+                Mask::from_positions(size, &region)
+            }
+            Select::Color(color) => {
+                let target = match color.or_else(|| self.sample(self.cursor.position)) {
+                    Some(target) => target,
+                    None => return,
+                };
+
+                let positions = self.layers()[self.layer]
+                    .positions()
+                    .filter(|pos| match self.sample(*pos) {
+                        Some(p) => color::matches(p, target, tolerance),
+                        None => false,
+                    })
+                    .collect::<Vec<_>>();
+
+                Mask::from_positions(size, &positions)
+            }
+            Select::Visual => match self.selection {
+                Some(selection) => selection.mask(),
+                None => return,
+            },
+            Select::All => {
+                let mut mask = Mask::new(size);
+                mask.invert();
+                mask
+            }
+            Select::None => Mask::new(size),
+            Select::Invert => {
+                let mut mask = self.mask.take().unwrap_or_else(|| Mask::new(size));
+                mask.invert();
+                mask
+            }
+        };
+
+        let mut current = self.mask.take().unwrap_or_else(|| Mask::new(size));
+        current.combine(&mask, op);
+
+        self.mask = match current.is_empty() {
+            true => None,
+            false => Some(current),
+        };
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Composite -
+    //     The colour at a position with all the layers blended together
+    // -----------------------------------------------------------------------------
+    fn composite(&self, pos: Position<i32>) -> Option<Pixel> {
+        self.composite_frame(self.frame, pos)
+    }
+
+    fn composite_frame(&self, frame: usize, pos: Position<i32>) -> Option<Pixel> {
+        self.frames[frame].layers.iter().try_fold(Pixel::transparent(), |dst, layer| {
+            layer.pixel(pos).map(|src| color::blend(dst, src))
+        })
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Flatten -
+    //     Every pixel of a frame with the layers blended together
+    // -----------------------------------------------------------------------------
+    fn flatten(&self, frame: usize) -> Vec<Pixel> {
+        self.frames[frame].layers[0]
+            .positions()
+            .map(|pos| self.composite_frame(frame, pos).unwrap_or(Pixel::transparent()))
+            .collect()
+    }
+
+    fn export(&mut self, path: &str) {
+        self.commit_floating();
+
+        let images = (0..self.frames.len())
+            .map(|frame| Image {
+                pixels: self.flatten(frame),
+                duration: self.frames[frame].duration,
+            })
+            .collect::<Vec<_>>();
+
+        let size = self.layers()[0].size();
+        let (loops, sheet) = (self.options.loops, &self.options.sheet);
+        let res = export::export(Path::new(path), size, &images, &self.tags, loops, sheet);
+        if let Err(e) = res {
+            self.message = Some(format!("export: {}", e));
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Timelapse -
+    //     Export the drawing process, replayed from the undo history
+    // -----------------------------------------------------------------------------
+    fn timelapse(&mut self, path: &str, every: usize) {
+        self.commit_floating();
+
+        if self.history.edits().is_empty() {
+            self.message = Some("timelapse: nothing to replay".to_string());
+            return;
+        }
+
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| f.layers.iter().map(|l| l.pixels().to_vec()).collect())
+            .collect();
+
+        let size = self.layers()[0].size();
+        let images = timelapse::replay(frames, size, self.history.edits(), every);
+
+        let (loops, sheet) = (self.options.loops, &self.options.sheet);
+        let res = export::export(Path::new(path), size, &images, &[], loops, sheet);
+        if let Err(e) = res {
+            self.message = Some(format!("timelapse: {}", e));
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Import -
+    //     Replace the document with the cells of a sprite sheet, one per frame
+    // -----------------------------------------------------------------------------
+    fn import(&mut self, import: Import) {
+        if self.keeps_changes("import", import.force) {
+            return;
+        }
+
+        let path = Path::new(&import.path);
+        let sheet = match import::read_png(path) {
+            Ok(sheet) => sheet,
+            Err(e) => {
+                self.message = Some(format!("import: {}", e));
+                return;
+            }
+        };
+
+        let cells = import::slice(sheet, import.cell, import.margin, import.spacing);
+        if cells.is_empty() {
+            self.message = Some(format!("import: no cells with any pixels in {}", import.path));
+            return;
+        }
+
+        let frames = cells
+            .into_iter()
+            .map(|pixels| Frame::new(vec![Layer::from_pixels(import.cell, pixels)]))
+            .collect();
+
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("sheet");
+        self.load(import.cell, frames, vec![name.to_string()], Vec::new());
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Keeps changes -
+    //     Opening another document throws the current one away,
+    //     so that is refused while it has unsaved changes (unless forced with !).
+    // -----------------------------------------------------------------------------
+    fn keeps_changes(&mut self, command: &str, force: bool) -> bool {
+        if !self.modified || force {
+            return false;
+        }
+
+        self.message = Some(format!("{}: unsaved changes (add ! to discard them)", command));
+        true
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Open / Write aseprite -
+    // -----------------------------------------------------------------------------
+    fn open(&mut self, path: &str, force: bool) {
+        if self.keeps_changes("edit", force) {
+            return;
+        }
+
+        if !aseprite::is_aseprite(Path::new(path)) {
+            self.message = Some("edit: can only open .ase and .aseprite files".to_string());
+            return;
+        }
+
+        let doc = match aseprite::read(Path::new(path)) {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.message = Some(format!("edit: {}", e));
+                return;
+            }
+        };
+
+        if doc.frames.is_empty() || doc.layer_names.is_empty() {
+            self.message = Some(format!("edit: {} has no image layers", path));
+            return;
+        }
+
+        let size = doc.size;
+        let frames = doc
+            .frames
+            .into_iter()
+            .map(|frame| {
+                let layers = frame
+                    .layers
+                    .into_iter()
+                    .zip(frame.links)
+                    .map(|(pixels, link)| {
+                        let mut layer = Layer::from_pixels(size, pixels);
+                        layer.link = link;
+                        layer
+                    })
+                    .collect();
+                Frame {
+                    duration: frame.duration.max(1),
+                    ..Frame::new(layers)
+                }
+            })
+            .collect();
+
+        self.load(size, frames, doc.layer_names, doc.tags);
+    }
+
+    fn write_aseprite(&mut self, path: &str) {
+        self.commit_floating();
+
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| DocumentFrame {
+                layers: frame.layers.iter().map(|l| l.pixels().to_vec()).collect(),
+                links: frame.layers.iter().map(|l| l.link).collect(),
+                duration: frame.duration,
+            })
+            .collect();
+
+        let doc = Document {
+            size: self.layers()[0].size(),
+            layer_names: self.layer_names.clone(),
+            frames,
+            tags: self.tags.clone(),
+        };
+
+        match aseprite::write(Path::new(path), &doc) {
+            Ok(()) => self.modified = false,
+            Err(e) => self.message = Some(format!("write: {}", e)),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Load -
+    //     Replace the whole document. Everything that refers to the old one
+    //     (history, selection, floating pixels...) is dropped.
+    // -----------------------------------------------------------------------------
+    fn load(&mut self, size: Size<i32>, frames: Vec<Frame>, layer_names: Vec<String>, tags: Vec<Tag>) {
+        let old_size = self.sprite.size;
+
+        self.frames = frames;
+        self.frame = 0;
+        self.layer_names = layer_names;
+        self.tags = tags;
+        self.next_link = self
+            .frames
+            .iter()
+            .flat_map(|f| f.layers.iter().filter_map(|l| l.link))
+            .max()
+            .unwrap_or(0);
+        self.layer = 0;
+        self.history = History::new();
+        self.insert_edit = Edit::new();
+        self.modified = false;
+        self.selection = None;
+        self.mask = None;
+        self.floating = None;
+        self.playback = None;
+        self.anchor = None;
+
+        // Keep the canvas centred
+        self.sprite.size = size;
+        self.sprite.position += old_size.to_vector() / 2 - size.to_vector() / 2;
+        self.cursor_sprite.size = size;
+        self.cursor_sprite.position = self.sprite.position;
+
+        self.cursor_texture = {
+            let pixels = Pixels::from_pixel(Pixel::transparent(), size.cast());
+            Texture::default_with_data(size, pixels.as_bytes())
+        };
+        self.highlights_stale = true;
+        self.marks_bounds = None;
+        self.floating_layer = Layer::new(size, Pixel::transparent());
+        self.onion_layer = Layer::new(size, Pixel::transparent());
+        self.save_buffer.resize(size);
+
+        self.goto_frame(0);
+    }
+
+    fn sample(&self, pos: Position<i32>) -> Option<Pixel> {
+        match self.options.sample {
+            Sample::Layer => self.layers()[self.layer].pixel(pos),
+            Sample::Composite => self.composite(pos),
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Fill -
+    //     Fill the region under the cursor with the foreground colour.
+    //     A global fill replaces every matching pixel, connected or not.
+    //     With a selection mask the fill stays inside of it.
+    //     In visual mode the selection is filled instead.
+    // -----------------------------------------------------------------------------
+    fn fill(&mut self, global: bool) {
+        // The layer has to be whole again before it is read
+        self.commit_floating();
+
+        let target = match self.sample(self.cursor.position) {
+            Some(target) => target,
+            None => return,
+        };
+
+        let tolerance = self.options.tolerance;
+        let mask = self.mask.as_ref();
+        let matches = |pos| match self.sample(pos) {
+            Some(_) if !mask.map(|m| m.contains(pos)).unwrap_or(true) => false,
+            Some(p) => color::matches(p, target, tolerance),
+            None => false,
+        };
+
+        let positions = match global {
+            true => self.layers()[self.layer]
+                .positions()
+                .filter(|pos| matches(*pos))
+                .collect(),
+            false => flood(
+                self.cursor.position,
+                self.layers()[self.layer].size(),
+                self.options.connectivity,
+                matches,
+            ),
+        };
+
+        self.paint(&positions);
+    }
+
+    fn fill_selection(&mut self) {
+        if let Some(mask) = self.selected() {
+            self.selection = None;
+            self.paint(&mask.positions());
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Yank / Delete / Paste -
+    //     Yanking only copies the pixels into the register, the layer
+    //     is left alone. Pasted pixels are left floating until committed.
+    //     Deleting yanks first, like in vim, and clears the selection.
+    //     Pasting puts the top left corner of the register at the cursor.
+    // -----------------------------------------------------------------------------
+    fn yank(&mut self) {
+        self.commit_floating();
+
+        if let Some(mask) = self.selected() {
+            if let Some((_, register)) = self.pixels_in(&mask) {
+                self.register = Some(register);
+            }
+            self.selection = None;
+        }
+
+        self.draw_overlay();
+    }
+
+    fn delete(&mut self) {
+        self.commit_floating();
+
+        let mask = match self.selected() {
+            Some(mask) => mask,
+            None => return,
+        };
+
+        if let Some((_, register)) = self.pixels_in(&mask) {
+            self.register = Some(register);
+        }
+
+        self.selection = None;
+        let pixels = mask
+            .positions()
+            .into_iter()
+            .map(|pos| (pos, Pixel::transparent()))
+            .collect();
+        self.put(pixels);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Lift -
+    //     Move the pixels under the mask from the current layer
+    //     into a floating selection.
+    // -----------------------------------------------------------------------------
+    fn lift(&mut self, mask: &Mask) {
+        let (origin, register) = match self.pixels_in(mask) {
+            Some(pixels) => pixels,
+            None => return,
+        };
+
+        let layer = self.layer_mut();
+        let lifted = mask
+            .positions()
+            .into_iter()
+            .filter_map(|pos| Some((pos, layer.set_pixel(pos, Pixel::transparent())?)))
+            .collect();
+        layer.sync();
+        self.layer_changed();
+
+        self.float(Floating::lifted(origin, register, lifted));
+        self.draw_onion();
+    }
+
+    fn lift_selected(&mut self) {
+        let mask = match self.selected() {
+            Some(mask) => mask,
+            None => {
+                let mut mask = Mask::new(self.layers()[self.layer].size());
+                mask.invert();
+                mask
+            }
+        };
+
+        self.selection = None;
+        self.lift(&mask);
+    }
+
+    fn paste(&mut self) {
+        self.commit_floating();
+
+        if let Some(register) = self.register.clone() {
+            self.float(Floating::new(self.cursor.position, register));
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Flip -
+    //     Flip the selection within the rectangle around it,
+    //     or the whole layer if there is no selection.
+    // -----------------------------------------------------------------------------
+    fn flip(&mut self, vertical: bool) {
+        if let Some(floating) = self.floating.as_mut() {
+            floating.flip(vertical);
+            self.draw_floating();
+            return;
+        }
+
+        let layer = &self.layers()[self.layer];
+        let mask = match self.selected() {
+            Some(mask) => mask,
+            None => {
+                let mut mask = Mask::new(layer.size());
+                mask.invert();
+                mask
+            }
+        };
+
+        let (origin, size) = match mask.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        let pixels = mask
+            .positions()
+            .into_iter()
+            .map(|pos| {
+                let offset = pos - origin;
+                let from = match vertical {
+                    true => Position::new(offset.x, size.height - 1 - offset.y),
+                    false => Position::new(size.width - 1 - offset.x, offset.y),
+                };
+                let from = origin + from;
+
+                let pixel = match mask.contains(from) {
+                    true => layer.pixel(from).unwrap_or(Pixel::transparent()),
+                    false => Pixel::transparent(),
+                };
+                (pos, pixel)
+            })
+            .collect();
+
+        self.put(pixels);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Shape -
+    //     Draw the current shape between the anchor and the cursor
+    // -----------------------------------------------------------------------------
+    fn commit_shape(&mut self) {
+        if let Some(anchor) = self.anchor.take() {
+            let positions = self.options.shape.positions(anchor, self.cursor.position);
+            let stroke = self.options.brush.stroke(&positions);
+            self.paint(&stroke);
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Mirrored -
+    //     The positions plus their mirror images (if mirroring is on)
+    // -----------------------------------------------------------------------------
+    fn mirrored(&self, positions: &[Position<i32>]) -> Vec<Position<i32>> {
+        let size = self.layers()[self.layer].size();
+        positions
+            .iter()
+            .flat_map(|pos| self.options.mirror.positions(*pos, size))
+            .collect()
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Gradient -
+    //     Dither from the foreground to the background colour
+    //     across the selection
+    // -----------------------------------------------------------------------------
+    fn gradient(&mut self, vertical: bool) {
+        let mask = match self.selected() {
+            Some(mask) => mask,
+            None => return,
+        };
+
+        let (origin, size) = match mask.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        let pixels = mask
+            .positions()
+            .into_iter()
+            .map(|pos| {
+                let (offset, length) = match vertical {
+                    true => (pos.y - origin.y, size.height),
+                    false => (pos.x - origin.x, size.width),
+                };
+
+                let t = match length {
+                    1 => 0.0,
+                    _ => offset as f32 / (length - 1) as f32,
+                };
+
+                (pos, dither::gradient(pos, t, self.options.fg, self.options.bg))
+            })
+            .collect();
+
+        self.write(pixels);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Paint -
+    //     Write the foreground colour to the current layer as one edit,
+    //     through the mirror and the pattern.
+    // -----------------------------------------------------------------------------
+    fn paint(&mut self, positions: &[Position<i32>]) {
+        let pixel = self.options.fg;
+        let pattern = &self.options.pattern;
+        let pixels = self
+            .mirrored(positions)
+            .into_iter()
+            .filter(|pos| pattern.paints(*pos))
+            .map(|pos| (pos, pixel))
+            .collect();
+
+        self.write(pixels);
+    }
+
+    fn write(&mut self, pixels: Vec<(Position<i32>, Pixel)>) {
+        self.commit_floating();
+
+        let index = self.layer;
+        let pixels = pixels
+            .into_iter()
+            .filter_map(|(pos, src)| {
+                let before = self.layers()[index].pixel(pos)?;
+                Some((pos, self.apply(before, src)?))
+            })
+            .collect();
+
+        self.put(pixels);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Put -
+    //     Replace pixels on the current layer as they are, as one edit
+    // -----------------------------------------------------------------------------
+    fn put(&mut self, pixels: Vec<(Position<i32>, Pixel)>) {
+        // Floating pixels are dropped before anything else changes the layer
+        self.commit_floating();
+
+        let (frame, index) = (self.frame, self.layer);
+        let layer = &mut self.layers_mut()[index];
+        let mut edit = Edit::new();
+
+        for (pos, after) in pixels {
+            if let Some(before) = layer.set_pixel(pos, after) {
+                edit.push((frame, index), pos, before, after);
+            }
+        }
+
+        layer.sync();
+        self.edit_linked(&mut edit);
+        self.push_edit(edit);
+        self.layer_changed();
+        self.draw_onion();
+        self.draw_overlay();
+    }
+
+    fn push_edit(&mut self, edit: Edit) {
+        if !edit.is_empty() {
+            self.modified = true;
+        }
+        self.history.push(edit);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Undo / Redo -
+    // -----------------------------------------------------------------------------
+    fn undo(&mut self) {
+        // Undoing while pixels float puts them back where they came from
+        if self.floating.is_some() {
+            return self.cancel_floating();
+        }
+
+        let edit = match self.history.undo() {
+            Some(edit) => edit,
+            None => return,
+        };
+
+        let frame = edit.frame().unwrap_or(self.frame);
+        let mut edited = Vec::new();
+        for change in edit.changes.iter().rev() {
+            self.frames[change.frame].layers[change.layer].set_pixel(change.position, change.before);
+            edited.push((change.frame, change.layer));
+        }
+
+        for link in &edit.links {
+            self.frames[link.frame].layers[link.layer].link = link.before;
+        }
+
+        self.modified = true;
+        self.show_edited_frame(frame, edited);
+    }
+
+    fn redo(&mut self) {
+        self.cancel_floating();
+
+        let edit = match self.history.redo() {
+            Some(edit) => edit,
+            None => return,
+        };
+
+        let frame = edit.frame().unwrap_or(self.frame);
+        let mut edited = Vec::new();
+        for change in &edit.changes {
+            self.frames[change.frame].layers[change.layer].set_pixel(change.position, change.after);
+            edited.push((change.frame, change.layer));
+        }
+
+        for link in &edit.links {
+            self.frames[link.frame].layers[link.layer].link = link.after;
+        }
+
+        self.modified = true;
+        self.show_edited_frame(frame, edited);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Substitute -
+    //     Replace one colour with another
+    // -----------------------------------------------------------------------------
+    fn substitute(&mut self, sub: Substitute) {
+        self.commit_floating();
+
+        let positions = match (sub.range, self.selected()) {
+            (Range::Selection, Some(mask)) => mask.positions(),
+            (Range::Selection, None) => {
+                let y = self.cursor.position.y;
+                (0..self.sprite.size.width)
+                    .map(|x| Position::new(x, y))
+                    .collect()
+            }
+            (Range::All, _) => self.layers()[self.layer].positions().collect(),
+        };
+
+        let layers = match sub.all_layers {
+            true => 0..self.layers().len(),
+            false => self.layer..self.layer + 1,
+        };
+
+        let frame = self.frame;
+        let mut edit = Edit::new();
+
+        for index in layers {
+            let layer = &mut self.layers_mut()[index];
+            for pos in &positions {
+                let before = match layer.pixel(*pos) {
+                    Some(p) => p,
+                    None => continue,
+                };
+
+                if color::matches(before, sub.from, sub.tolerance) {
+                    layer.set_pixel(*pos, sub.to);
+                    edit.push((frame, index), *pos, before, sub.to);
+                }
+            }
+            layer.sync();
+        }
+
+        self.edit_linked(&mut edit);
+        self.push_edit(edit);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Input handling -
+    // -----------------------------------------------------------------------------
+    pub fn input(&mut self, c: char, mode: Mode, input: &InputHandler) {
+        let entering_insert = match (self.mode, mode) {
+            (Mode::Insert(_), _) => false,
+            (_, Mode::Insert(_)) => true,
+            _ => false,
+        };
+        let entering_visual = !self.mode.visual_mode() && mode.visual_mode();
+        self.mode = mode;
+
+        if let Mode::Visual(kind) = mode {
+            match self.selection.as_mut() {
+                Some(selection) => selection.kind = kind,
+                None if entering_visual => {
+                    let bounds = self.layers()[self.layer].size();
+                    self.selection = Some(Selection::new(self.cursor.position, kind, bounds));
+                }
+                None => {}
+            }
+            self.draw_overlay();
+        }
+
+        if entering_insert {
+            self.commit_floating();
+            self.pen_down = true;
+
+            if let Mode::Insert(Insert::Append) = mode {
+                self.move_cursor(Position::new(1, 0));
+            }
+        }
+
+        let moved;
+
+        match mode {
+            Mode::Command => return,
+            Mode::Normal | Mode::Visual(_) | Mode::Insert(_) => {
+                // `[f` / `]f`: previous / next frame
+                let pending = self.pending.take();
+                if let Mode::Normal = mode {
+                    match (pending, c) {
+                        (Some('['), 'f') => return self.step_frame(-1),
+                        (Some(']'), 'f') => return self.step_frame(1),
+                        (_, '[') | (_, ']') => {
+                            self.pending = Some(c);
+                            return;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let action = input.to_action(c, mode);
+                moved = match action {
+                    Some(Action::Left) | Some(Action::Right) => true,
+                    Some(Action::Up) | Some(Action::Down) => true,
+                    _ => false,
+                };
+
+                match action {
+                    Some(Action::Left) => self.move_cursor(Position::new(-1, 0)),
+                    Some(Action::Right) => self.move_cursor(Position::new(1, 0)),
+                    Some(Action::Up) => self.move_cursor(Position::new(0, -1)),
+                    Some(Action::Down) => self.move_cursor(Position::new(0, 1)),
+                    Some(Action::Undo) => self.undo(),
+                    Some(Action::Redo) => self.redo(),
+                    Some(Action::SearchNext) => self.jump_to_match(true),
+                    Some(Action::SearchPrev) => self.jump_to_match(false),
+                    Some(Action::Fill) if self.selection.is_some() => self.fill_selection(),
+                    Some(Action::Fill) => self.fill(false),
+                    Some(Action::FillGlobal) => self.fill(true),
+                    Some(Action::Anchor) => {
+                        self.anchor = Some(self.cursor.position);
+                        self.draw_overlay();
+                    }
+                    Some(Action::Commit) if self.floating.is_some() => self.commit_floating(),
+                    Some(Action::Commit) => self.commit_shape(),
+                    Some(Action::RotateCw) => self.rotate(90),
+                    Some(Action::RotateCcw) => self.rotate(-90),
+                    Some(Action::Yank) => self.yank(),
+                    Some(Action::Delete) => self.delete(),
+                    Some(Action::Paste) => self.paste(),
+                    Some(Action::Pen) => {
+                        self.pen_down = !self.pen_down;
+                        self.stroke.clear();
+                    }
+                    Some(Action::SearchUnderCursor) => {
+                        if let Some(color) = self.layers()[self.layer].pixel(self.cursor.position) {
+                            self.search(color);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        match mode {
+            Mode::Insert(insert) => {
+                // Esc: the whole insert is undone in one go
+                if c == '\u{1b}' {
+                    self.end_insert();
+                    return;
+                }
+
+                let paint = match insert {
+                    Insert::Replace => moved,
+                    _ => true,
+                };
+
+                if paint && self.pen_down {
+                    self.draw(insert);
+                }
+
+                if let Insert::Once = insert {
+                    self.end_insert();
+                }
+            }
+            Mode::Visual(_) => {
+                if c == '\u{1b}' {
+                    self.selection = None;
+                    self.draw_overlay();
+                }
+            }
+            Mode::Normal => {
+                if self.selection.take().is_some() {
+                    self.draw_overlay();
+                }
+
+                if c == '\u{1b}' && self.anchor.take().is_some() {
+                    self.draw_overlay();
+                }
+
+                if c == '\u{1b}' {
+                    self.cancel_floating();
+                }
+            }
+            Mode::Command => {}
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - TODO -
+    //     * On save use the framebuffer rendere for all renders
+    //     * Set the framebuffer texture to the same size as the textures to save
+    //     * Set the framebuffer sprite to the same size as the textures to save
+    // -----------------------------------------------------------------------------
+    pub fn exec(&mut self, command: Command, context: &mut Context) {
+        match command {
+            Command::Write(path) if aseprite::is_aseprite(Path::new(&path)) => self.write_aseprite(&path),
+            Command::Write(path) => {
+                self.commit_floating();
+                let res = self.save_buffer.save(
+                    &self.sprite,
+                    self.layers(),
+                    &self.viewport,
+                    path,
+                    context,
+                );
+
+                // A png only holds everything with a single frame and layer
+                let whole = self.frames.len() == 1 && self.layer_names.len() == 1;
+                if res.is_ok() && whole {
+                    self.modified = false;
+                }
+
+                eprintln!("{:?}", res);
+            }
+            Command::Export(path) => self.export(&path),
+            Command::Timelapse { path, every } => self.timelapse(&path, every),
+            Command::Import(import) => self.import(import),
+            Command::Edit { path, force } => self.open(&path, force),
+            Command::Substitute(sub) => self.substitute(sub),
+            Command::Search(color) => self.search(color),
+            Command::NoHighlight => {
+                self.search = None;
+                self.highlights_stale = true;
+            }
+            Command::Brush(brush) => self.options.brush = brush,
+            Command::BrushFromSelection => self.brush_from_selection(),
+            Command::Gradient { vertical } => self.gradient(vertical),
+            Command::Flip { vertical } => self.flip(vertical),
+            Command::Rotate(degrees) => self.rotate(degrees),
+            Command::Scale(factor) => self.scale(factor),
+            Command::Select(select, op) => self.select(select, op),
+            Command::Frame(op) => self.frame_op(op),
+            Command::Play(tag) => self.play(tag),
+            Command::Stop => self.playback = None,
+            Command::Tag(tag) => self.tag(tag),
+            Command::Untag(name) => self.untag(&name),
+            Command::Tags => self.list_tags(),
+            Command::Link(first, last) => self.link(first, last),
+            Command::Unlink => self.unlink(),
+            Command::Invalid(message) => self.message = Some(message),
+            Command::Set(key, value) => {
+                // Alpha lock belongs to the current layer, not the options
+                let res = match key.as_str() {
+                    "alphalock" => parse_bool(&value)
+                        .map(|lock| self.layer_mut().alpha_lock = lock),
+                    _ => self.options.set(&key, &value),
+                };
+
+                if let Err(e) = res {
+                    self.message = Some(format!("set: {}", e));
+                }
+            }
+            _ => {}
+        }
+
+        // Commands run from visual mode apply to the selection,
+        // after which the selection is gone.
+        // Any of them can change the layer or the mask.
+        self.selection = None;
+        self.highlights_stale = true;
+        self.draw_onion();
+        self.draw_overlay();
+    }
+}
+
+// The rectangle around the positions, inside of `size`
+fn bounds(positions: impl Iterator<Item = Position<i32>>, size: Size<i32>) -> Option<(Position<i32>, Size<i32>)> {
+    let inside = positions.filter(|p| p.x >= 0 && p.y >= 0 && p.x < size.width && p.y < size.height);
+    let (min, max) = inside.fold(None, |acc: Option<(Position<i32>, Position<i32>)>, p| match acc {
+        None => Some((p, p)),
+        Some((min, max)) => Some((
+            Position::new(min.x.min(p.x), min.y.min(p.y)),
+            Position::new(max.x.max(p.x), max.y.max(p.y)),
+        )),
+    })?;
+
+    Some((min, Size::new(max.x - min.x + 1, max.y - min.y + 1)))
+}
+
+fn union(
+    a: Option<(Position<i32>, Size<i32>)>,
+    b: Option<(Position<i32>, Size<i32>)>,
+) -> Option<(Position<i32>, Size<i32>)> {
+    match (a, b) {
+        (Some((a, a_size)), Some((b, b_size))) => {
+            let min = Position::new(a.x.min(b.x), a.y.min(b.y));
+            let max = Position::new(
+                (a.x + a_size.width).max(b.x + b_size.width),
+                (a.y + a_size.height).max(b.y + b_size.height),
+            );
+            Some((min, Size::new(max.x - min.x, max.y - min.y)))
+        }
+        (a, None) => a,
+        (None, b) => b,
     }
 }
@@ -0,0 +1,71 @@
+use nightmaregl::Pixel;
+
+// -----------------------------------------------------------------------------
+//     - Parse hex -
+//     Accepts `#rrggbb` and `#rrggbbaa`.
+//     The alpha channel defaults to fully opaque.
+// -----------------------------------------------------------------------------
+pub fn parse_hex(s: &str) -> Option<Pixel> {
+    let hex = s.trim().strip_prefix('#')?;
+
+    if !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+    match hex.len() {
+        6 => Some(Pixel {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a: 255,
+        }),
+        8 => Some(Pixel {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a: channel(6)?,
+        }),
+        _ => None,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Tolerance -
+//     Two pixels match if no channel differs by more than the tolerance.
+// -----------------------------------------------------------------------------
+pub fn matches(a: Pixel, b: Pixel, tolerance: u8) -> bool {
+    let diff = |a: u8, b: u8| (a as i16 - b as i16).abs() as u8;
+
+    diff(a.r, b.r) <= tolerance
+        && diff(a.g, b.g) <= tolerance
+        && diff(a.b, b.b) <= tolerance
+        && diff(a.a, b.a) <= tolerance
+}
+
+// -----------------------------------------------------------------------------
+//     - Blend -
+//     Composite `src` over `dst` (straight, not premultiplied alpha)
+// -----------------------------------------------------------------------------
+pub fn blend(dst: Pixel, src: Pixel) -> Pixel {
+    let src_a = src.a as f32 / 255.0;
+    let dst_a = dst.a as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+
+    if out_a <= 0.0 {
+        return Pixel::transparent();
+    }
+
+    let channel = |s: u8, d: u8| {
+        let c = (s as f32 * src_a + d as f32 * dst_a * (1.0 - src_a)) / out_a;
+        c.round() as u8
+    };
+
+    Pixel {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: (out_a * 255.0).round() as u8,
+    }
+}
@@ -3,7 +3,13 @@ use nightmaregl::{
     Context, Pixel, Pixels, Position, Renderer, Result, Size, Sprite, Texture, VertexData, Viewport,
 };
 
+use crate::brush::{self, Brush};
+use crate::color::parse_hex;
+use crate::floating;
+use crate::frame::{Direction, Tag};
 use crate::input::InputHandler;
+use crate::options::parse_range;
+use crate::selection::MaskOp;
 use crate::Mode;
 
 const FONT_SIZE: f32 = 18.0;
@@ -15,9 +21,113 @@ const FONT_SIZE: f32 = 18.0;
 pub enum Command {
     Quit,
     Write(String),
+    Edit { path: String, force: bool },
+    Export(String),
+    Timelapse { path: String, every: usize },
+    Import(Import),
+    Substitute(Substitute),
+    Search(Pixel),
+    NoHighlight,
+    Set(String, String),
+    Brush(Brush),
+    BrushFromSelection,
+    Gradient { vertical: bool },
+    Flip { vertical: bool },
+    Rotate(i32),
+    Scale(i32),
+    Select(Select, MaskOp),
+    Frame(FrameOp),
+    Play(Option<String>),
+    Stop,
+    Tag(Tag),
+    Untag(String),
+    Tags,
+    Link(usize, usize),
+    Unlink,
+    // Couldn't be parsed, with the reason shown on the message line
+    Invalid(String),
     Noop,
 }
 
+// -----------------------------------------------------------------------------
+//     - Range -
+//     `Selection` is the visual selection, or the cursor row
+//     if there is no selection (like the current line in vim).
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Range {
+    Selection,
+    All,
+}
+
+// -----------------------------------------------------------------------------
+//     - Select -
+//     :select wand [+|-|&]
+//     :select color [#rrggbb] [+|-|&]
+//     :select visual [+|-|&]
+//     :select all|none|invert
+//
+//     `+` adds to the current mask, `-` subtracts from it
+//     and `&` keeps what is in both. Without one the mask is replaced.
+//     Without a colour the colour under the cursor is used.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Select {
+    Wand,
+    Color(Option<Pixel>),
+    Visual,
+    All,
+    None,
+    Invert,
+}
+
+// -----------------------------------------------------------------------------
+//     - Import -
+//     :import[!] sheet.png 16x16 [margin] [spacing]
+//     With ! unsaved changes are thrown away.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: String,
+    pub cell: Size<i32>,
+    pub margin: i32,
+    pub spacing: i32,
+    pub force: bool,
+}
+
+// -----------------------------------------------------------------------------
+//     - Frame op -
+//     :frame new|dup|delete
+//     :frame <n> (counting from 1)
+//     :frame duration <ms>
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum FrameOp {
+    New,
+    Duplicate,
+    Delete,
+    Goto(usize),
+    Duration(u32),
+}
+
+// -----------------------------------------------------------------------------
+//     - Substitute -
+//     :s/#ff0000/#00ff00/
+//     :%s/#ff0000/#00ff00/l16
+//
+//     Flags:
+//     * l: all layers, not just the current one
+//     * a number: tolerance (max difference per channel)
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct Substitute {
+    pub from: Pixel,
+    pub to: Pixel,
+    pub range: Range,
+    pub all_layers: bool,
+    pub tolerance: u8,
+}
+
 // -----------------------------------------------------------------------------
 //     - Cursor -
 // -----------------------------------------------------------------------------
@@ -41,6 +151,8 @@ impl Cursor {
 // -----------------------------------------------------------------------------
 pub struct CommandInput {
     text: Text,
+    indicator: Text,
+    indicator_label: String,
     text_renderer: Renderer<VertexData>,
     cursor_renderer: Renderer<VertexData>,
     viewport: Viewport,
@@ -70,8 +182,14 @@ impl CommandInput {
         let mut text = Text::from_path(font_path, FONT_SIZE, WordWrap::NoWrap, context)?;
         text.position(Position::new(0.0, FONT_SIZE / 1.5));
 
+        // Mode indicator, shown in place of the command line
+        let mut indicator = Text::from_path(font_path, FONT_SIZE, WordWrap::NoWrap, context)?;
+        indicator.position(Position::new(0.0, FONT_SIZE / 1.5));
+
         let mut inst = Self {
             text,
+            indicator,
+            indicator_label: String::new(),
             text_renderer,
             cursor_renderer,
             viewport,
@@ -100,8 +218,16 @@ impl CommandInput {
         self.cursor.sprite.position = Position::new(self.text.caret().x, FONT_SIZE / 3.0);
     }
 
+    pub fn set_indicator(&mut self, label: &str) {
+        if self.indicator_label != label {
+            self.indicator_label = label.to_string();
+            self.indicator.set_text(label);
+        }
+    }
+
     pub fn render(&self, context: &mut Context, mode: Mode) {
         if !mode.command_mode() {
+            self.render_indicator(context);
             return;
         }
 
@@ -129,10 +255,27 @@ impl CommandInput {
 
     }
 
+    fn render_indicator(&self, context: &mut Context) {
+        if self.indicator_label.is_empty() {
+            return;
+        }
+
+        let res = self.text_renderer.render(
+            self.indicator.texture(),
+            &self.indicator.vertex_data(),
+            &self.viewport,
+            context,
+        );
+
+        if let Err(e) = res {
+            eprintln!("indicator renderer: {:?}", e);
+        }
+    }
+
     pub fn input(&mut self, c: char, mode: Mode, input: &InputHandler) -> Command {
         match mode {
             Mode::Command => {}
-            Mode::Insert | Mode::Normal | Mode::Visual => return Command::Noop,
+            Mode::Insert(_) | Mode::Normal | Mode::Visual(_) => return Command::Noop,
         }
 
         match c {
@@ -171,6 +314,26 @@ fn parse_command(s: String) -> Command {
         return Command::Quit;
     }
 
+    if let Some(command) = parse_timelapse(&s) {
+        return command;
+    }
+
+    if let Some(path) = s.strip_prefix(":export ") {
+        return Command::Export(path.trim().to_string());
+    }
+
+    // With ! unsaved changes are thrown away
+    let edit = [":e ", ":edit ", ":e! ", ":edit! "]
+        .iter()
+        .find_map(|prefix| Some((s.strip_prefix(prefix)?, prefix.contains('!'))));
+
+    if let Some((path, force)) = edit {
+        return Command::Edit {
+            path: path.trim().to_string(),
+            force,
+        };
+    }
+
     if s.starts_with(":w") {
         return Command::Write(
             s.split_whitespace()
@@ -182,5 +345,415 @@ fn parse_command(s: String) -> Command {
         );
     }
 
-    Command::Noop
+    if s == ":play" {
+        return Command::Play(None);
+    }
+
+    if let Some(tag) = s.strip_prefix(":play ") {
+        return Command::Play(Some(tag.trim().to_string()));
+    }
+
+    if s == ":tags" {
+        return Command::Tags;
+    }
+
+    if s == ":unlink" {
+        return Command::Unlink;
+    }
+
+    if let Some(range) = s.strip_prefix(":link ") {
+        return match parse_range(range.trim()) {
+            Ok(Some((first, last))) => Command::Link(first, last),
+            _ => Command::Invalid(format!("link: not a range of frames: {}", range.trim())),
+        };
+    }
+
+    if let Some(name) = s.strip_prefix(":untag ") {
+        return Command::Untag(name.trim().to_string());
+    }
+
+    if s == ":stop" {
+        return Command::Stop;
+    }
+
+    if s == ":noh" || s == ":nohlsearch" {
+        return Command::NoHighlight;
+    }
+
+    if s == ":gradient" || s == ":gradient h" {
+        return Command::Gradient { vertical: false };
+    }
+
+    if s == ":gradient v" {
+        return Command::Gradient { vertical: true };
+    }
+
+    if s == ":flip" || s == ":flip h" {
+        return Command::Flip { vertical: false };
+    }
+
+    if s == ":flip v" {
+        return Command::Flip { vertical: true };
+    }
+
+    if let Some(degrees) = s.strip_prefix(":rotate ") {
+        return match degrees.trim().parse() {
+            Ok(degrees) => Command::Rotate(degrees),
+            Err(_) => Command::Invalid(format!("rotate: not a whole number: {}", degrees.trim())),
+        };
+    }
+
+    if let Some(factor) = s.strip_prefix(":scale ") {
+        return match factor.trim().parse() {
+            Ok(factor) if factor > 0 && factor <= floating::MAX_SCALE => Command::Scale(factor),
+            _ => Command::Invalid(format!(
+                "scale: the factor is a whole number from 1 to {}",
+                floating::MAX_SCALE
+            )),
+        };
+    }
+
+    if let Some(option) = s.strip_prefix(":set ") {
+        let mut parts = option.trim().splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim().to_string();
+        let value = parts.next().unwrap_or("").trim().to_string();
+        return Command::Set(key, value);
+    }
+
+    if let Some(color) = s.strip_prefix('/') {
+        return match parse_hex(color) {
+            Some(color) => Command::Search(color),
+            None => Command::Invalid(format!("search: not a colour: {}", color)),
+        };
+    }
+
+    if let Some(command) = parse_substitute(&s) {
+        return command;
+    }
+
+    if let Some(command) = parse_brush(&s) {
+        return command;
+    }
+
+    if let Some(command) = parse_select(&s) {
+        return command;
+    }
+
+    if let Some(command) = parse_frame(&s) {
+        return command;
+    }
+
+    if let Some(command) = parse_tag(&s) {
+        return command;
+    }
+
+    if let Some(command) = parse_import(&s) {
+        return command;
+    }
+
+    match s.as_str() {
+        "" | ":" => Command::Noop,
+        _ => Command::Invalid(format!("not a command: {}", s)),
+    }
+}
+
+fn parse_substitute(s: &str) -> Option<Command> {
+    let (range, rest) = match s.strip_prefix(":%s") {
+        Some(rest) => (Range::All, rest),
+        None => (Range::Selection, s.strip_prefix(":s")?),
+    };
+
+    let mut parts = rest.strip_prefix('/')?.split('/');
+    let from = parse_hex(parts.next()?)?;
+    let to = parse_hex(parts.next()?)?;
+    let flags = parts.next().unwrap_or("");
+
+    let all_layers = flags.contains('l');
+    let tolerance = flags
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    let tolerance = match tolerance.is_empty() {
+        true => 0,
+        false => tolerance.parse().ok()?,
+    };
+
+    let sub = Substitute {
+        from,
+        to,
+        range,
+        all_layers,
+        tolerance,
+    };
+
+    Some(Command::Substitute(sub))
+}
+
+fn parse_brush(s: &str) -> Option<Command> {
+    let mut args = s.strip_prefix(":brush ")?.split_whitespace();
+    let kind = args.next()?;
+
+    if kind == "from-selection" {
+        return Some(Command::BrushFromSelection);
+    }
+
+    if kind == "custom" {
+        return Brush::custom(args.next()?).map(Command::Brush);
+    }
+
+    let size = match args.next() {
+        Some(size) => size.parse().ok()?,
+        None => 1,
+    };
+
+    if size < 1 || size > brush::MAX_SIZE {
+        return None;
+    }
+
+    match kind {
+        "square" => Some(Command::Brush(Brush::square(size))),
+        "round" => Some(Command::Brush(Brush::round(size))),
+        _ => None,
+    }
+}
+
+fn parse_select(s: &str) -> Option<Command> {
+    let mut args = s.strip_prefix(":select ")?.split_whitespace().peekable();
+
+    let select = match args.next()? {
+        "wand" => Select::Wand,
+        "color" => {
+            let color = match args.peek() {
+                Some(arg) if arg.starts_with('#') => Some(parse_hex(args.next()?)?),
+                _ => None,
+            };
+            Select::Color(color)
+        }
+        "visual" => Select::Visual,
+        "all" => Select::All,
+        "none" => Select::None,
+        "invert" => Select::Invert,
+        _ => return None,
+    };
+
+    let op = match (select, args.next()) {
+        (Select::None, _) | (Select::Invert, _) => MaskOp::Replace,
+        (_, None) => MaskOp::Replace,
+        (_, Some("+")) => MaskOp::Union,
+        (_, Some("-")) => MaskOp::Subtract,
+        (_, Some("&")) => MaskOp::Intersect,
+        _ => return None,
+    };
+
+    Some(Command::Select(select, op))
+}
+
+fn parse_frame(s: &str) -> Option<Command> {
+    let mut args = s.strip_prefix(":frame ")?.split_whitespace();
+
+    let op = match args.next()? {
+        "new" => FrameOp::New,
+        "dup" => FrameOp::Duplicate,
+        "delete" => FrameOp::Delete,
+        "duration" => match args.next()?.parse().ok()? {
+            0 => return None,
+            ms => FrameOp::Duration(ms),
+        },
+        n => FrameOp::Goto(n.parse().ok()?),
+    };
+
+    Some(Command::Frame(op))
+}
+
+// -----------------------------------------------------------------------------
+//     - Tag -
+//     :tag <name> <first>-<last> [forward|reverse|pingpong]
+//     Frames count from 1.
+// -----------------------------------------------------------------------------
+fn parse_tag(s: &str) -> Option<Command> {
+    let mut args = s.strip_prefix(":tag ")?.split_whitespace();
+    let name = args.next()?.to_string();
+    let (from, to) = parse_range(args.next()?).ok()??;
+    let direction = match args.next() {
+        Some(direction) => Direction::from_str(direction).ok()?,
+        None => Direction::Forward,
+    };
+
+    let tag = Tag {
+        name,
+        from: from - 1,
+        to: to - 1,
+        direction,
+    };
+
+    Some(Command::Tag(tag))
+}
+
+fn parse_import(s: &str) -> Option<Command> {
+    let (args, force) = match s.strip_prefix(":import! ") {
+        Some(args) => (args, true),
+        None => (s.strip_prefix(":import ")?, false),
+    };
+    let mut args = args.split_whitespace();
+    let path = args.next()?.to_string();
+
+    let (width, height) = args.next()?.split_once('x')?;
+    let cell = Size::new(width.parse().ok()?, height.parse().ok()?);
+    if cell.width < 1 || cell.height < 1 {
+        return None;
+    }
+
+    let mut number = || match args.next() {
+        Some(n) => n.parse::<u16>().ok().map(i32::from),
+        None => Some(0),
+    };
+    let margin = number()?;
+    let spacing = number()?;
+
+    let import = Import {
+        path,
+        cell,
+        margin,
+        spacing,
+        force,
+    };
+
+    Some(Command::Import(import))
+}
+
+// -----------------------------------------------------------------------------
+//     - Timelapse -
+//     :timelapse out.gif [--every <n>]
+// -----------------------------------------------------------------------------
+const TIMELAPSE_USAGE: &str = "timelapse: usage :timelapse <path> [--every <n>]";
+
+fn parse_timelapse(s: &str) -> Option<Command> {
+    if s != ":timelapse" && !s.starts_with(":timelapse ") {
+        return None;
+    }
+
+    let mut args = s.split_whitespace().skip(1);
+    let path = match args.next() {
+        Some(path) => path.to_string(),
+        None => return Some(Command::Invalid(TIMELAPSE_USAGE.to_string())),
+    };
+
+    let every = match (args.next(), args.next(), args.next()) {
+        (None, _, _) => 1,
+        (Some("--every"), Some(n), None) => match n.parse() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                let message = format!("timelapse: --every takes a whole number above 0, not {}", n);
+                return Some(Command::Invalid(message));
+            }
+        },
+        _ => return Some(Command::Invalid(TIMELAPSE_USAGE.to_string())),
+    };
+
+    Some(Command::Timelapse { path, every })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Command {
+        parse_command(s.to_string())
+    }
+
+    #[test]
+    fn substitute() {
+        let sub = match parse(":%s/#ff0000/#00ff00/l8") {
+            Command::Substitute(sub) => sub,
+            c => panic!("{:?}", c),
+        };
+
+        assert!(matches!(sub.range, Range::All));
+        assert_eq!(sub.from, Pixel { r: 255, g: 0, b: 0, a: 255 });
+        assert_eq!(sub.to, Pixel { r: 0, g: 255, b: 0, a: 255 });
+        assert!(sub.all_layers);
+        assert_eq!(sub.tolerance, 8);
+    }
+
+    #[test]
+    fn scale() {
+        assert!(matches!(parse(":scale 2"), Command::Scale(2)));
+        assert!(matches!(parse(":scale 0"), Command::Invalid(_)));
+        assert!(matches!(parse(":scale 100000"), Command::Invalid(_)));
+    }
+
+    #[test]
+    fn frames() {
+        assert!(matches!(parse(":frame new"), Command::Frame(FrameOp::New)));
+        assert!(matches!(parse(":frame dup"), Command::Frame(FrameOp::Duplicate)));
+        assert!(matches!(parse(":frame 3"), Command::Frame(FrameOp::Goto(3))));
+        assert!(matches!(parse(":frame duration 120"), Command::Frame(FrameOp::Duration(120))));
+        assert!(matches!(parse(":frame duration 0"), Command::Invalid(_)));
+    }
+
+    #[test]
+    fn tags() {
+        let tag = match parse(":tag walk 2-5 pingpong") {
+            Command::Tag(tag) => tag,
+            c => panic!("{:?}", c),
+        };
+        assert_eq!(tag.name, "walk");
+        assert_eq!((tag.from, tag.to), (1, 4));
+        assert_eq!(tag.direction, Direction::PingPong);
+
+        assert!(matches!(parse(":tag walk 5-2"), Command::Invalid(_)));
+        assert!(matches!(parse(":untag walk"), Command::Untag(name) if name == "walk"));
+    }
+
+    #[test]
+    fn import() {
+        let import = match parse(":import sheet.png 16x8 1 2") {
+            Command::Import(import) => import,
+            c => panic!("{:?}", c),
+        };
+        assert_eq!(import.path, "sheet.png");
+        assert_eq!((import.cell.width, import.cell.height), (16, 8));
+        assert_eq!((import.margin, import.spacing), (1, 2));
+        assert!(!import.force);
+        assert!(matches!(parse(":import! sheet.png 16x8"), Command::Import(i) if i.force));
+
+        assert!(matches!(parse(":import sheet.png 0x8"), Command::Invalid(_)));
+    }
+
+    #[test]
+    fn edit_and_write() {
+        assert!(matches!(parse(":w walk.ase"), Command::Write(p) if p == "walk.ase"));
+        assert!(matches!(parse(":e walk.ase"), Command::Edit { path, force: false } if path == "walk.ase"));
+        assert!(matches!(parse(":edit walk.ase"), Command::Edit { path, force: false } if path == "walk.ase"));
+        assert!(matches!(parse(":e! walk.ase"), Command::Edit { path, force: true } if path == "walk.ase"));
+        assert!(matches!(parse(":edit! walk.ase"), Command::Edit { force: true, .. }));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(parse(":"), Command::Noop));
+        let invalid = parse(":frobnicate");
+        assert!(matches!(invalid, Command::Invalid(m) if m == "not a command: :frobnicate"));
+        assert!(matches!(parse(":rotate left"), Command::Invalid(_)));
+        assert!(matches!(parse(":link x"), Command::Invalid(_)));
+        assert!(matches!(parse("/#zzzzzz"), Command::Invalid(_)));
+    }
+
+    #[test]
+    fn link() {
+        assert!(matches!(parse(":link 1-3"), Command::Link(1, 3)));
+        assert!(matches!(parse(":unlink"), Command::Unlink));
+    }
+
+    #[test]
+    fn timelapse() {
+        assert!(matches!(
+            parse(":timelapse out.gif"),
+            Command::Timelapse { path, every: 1 } if path == "out.gif"
+        ));
+        assert!(matches!(parse(":timelapse out.gif --every 10"), Command::Timelapse { every: 10, .. }));
+        assert!(matches!(parse(":timelapse out.gif --every 0"), Command::Invalid(_)));
+        assert!(matches!(parse(":timelapse out.gif --every ten"), Command::Invalid(_)));
+        assert!(matches!(parse(":timelapse out.gif stray"), Command::Invalid(_)));
+    }
 }
@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+use nightmaregl::{Pixel, Position};
+
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn bayer(pos: Position<i32>) -> u8 {
+    BAYER[pos.y.rem_euclid(8) as usize][pos.x.rem_euclid(8) as usize]
+}
+
+// -----------------------------------------------------------------------------
+//     - Pattern -
+//     :set pattern=off|checker|25|50|75
+//     :set pattern=#.#./.#.#/#.#./.#.#
+//
+//     Patterns are anchored to the canvas (not the brush)
+//     so dabs next to each other line up.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Solid,
+    Checker,
+    Bayer(u8),
+    Custom { size: i32, mask: Vec<bool> },
+}
+
+impl Pattern {
+    pub fn from_str(s: &str) -> Result<Self> {
+        let pattern = match s {
+            "off" | "solid" => Pattern::Solid,
+            "checker" => Pattern::Checker,
+            "25" => Pattern::Bayer(16),
+            "50" => Pattern::Bayer(32),
+            "75" => Pattern::Bayer(48),
+            mask => {
+                let rows = mask.split('/').collect::<Vec<_>>();
+                let size = rows.len();
+                let valid = rows
+                    .iter()
+                    .all(|r| r.chars().count() == size && r.chars().all(|c| c == '#' || c == '.'));
+                if (size != 4 && size != 8) || !valid {
+                    bail!("pattern is one of off, checker, 25, 50, 75 or a 4x4 / 8x8 mask of # and .");
+                }
+
+                let mask = rows.iter().flat_map(|r| r.chars().map(|c| c == '#')).collect();
+                Pattern::Custom {
+                    size: size as i32,
+                    mask,
+                }
+            }
+        };
+
+        Ok(pattern)
+    }
+
+    pub fn paints(&self, pos: Position<i32>) -> bool {
+        match self {
+            Pattern::Solid => true,
+            Pattern::Checker => (pos.x + pos.y).rem_euclid(2) == 0,
+            Pattern::Bayer(threshold) => bayer(pos) < *threshold,
+            Pattern::Custom { size, mask } => {
+                let x = pos.x.rem_euclid(*size);
+                let y = pos.y.rem_euclid(*size);
+                mask[(y * size + x) as usize]
+            }
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Gradient -
+//     Ordered dither from `from` to `to`, where `t` is how far
+//     along the gradient the position is (0.0 to 1.0).
+// -----------------------------------------------------------------------------
+pub fn gradient(pos: Position<i32>, t: f32, from: Pixel, to: Pixel) -> Pixel {
+    let threshold = (bayer(pos) as f32 + 0.5) / 64.0;
+    match t > threshold {
+        true => to,
+        false => from,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_mask() {
+        let pattern = Pattern::from_str("#.../.#../..#./...#").unwrap();
+        assert!(pattern.paints(Position::new(0, 0)));
+        assert!(pattern.paints(Position::new(5, 5)));
+        assert!(!pattern.paints(Position::new(1, 0)));
+    }
+
+    #[test]
+    fn bad_masks() {
+        assert!(Pattern::from_str("#.../.#../..#.").is_err());
+        assert!(Pattern::from_str("#x../.#../..#./...#").is_err());
+        assert!(Pattern::from_str("#é./.#../..#./...#").is_err());
+        assert!(Pattern::from_str("#é../.#../..#./...#").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use nightmaregl::{Pixel, Size};
+
+use crate::frame::Tag;
+use crate::sheet::{self, SheetOptions};
+
+// -----------------------------------------------------------------------------
+//     - Image -
+//     A flattened frame: every layer blended together, in reading order
+// -----------------------------------------------------------------------------
+pub struct Image {
+    pub pixels: Vec<Pixel>,
+    pub duration: u32,
+}
+
+// -----------------------------------------------------------------------------
+//     - Export -
+//     Write every frame. The format comes from the extension:
+//     * .gif: animated gif
+//     * .apng: animated png
+//     * .png: sprite sheet, with the metadata in a json file next to it
+//
+//     `loops` is the number of times to play in total (so 1 plays once),
+//     0 plays forever.
+// -----------------------------------------------------------------------------
+pub fn export(
+    path: &Path,
+    size: Size<i32>,
+    images: &[Image],
+    tags: &[Tag],
+    loops: u16,
+    sheet: &SheetOptions,
+) -> Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("gif") => gif(path, size, images, loops),
+        Some("apng") => apng(path, size, images, loops),
+        Some("png") => sheet::export(path, size, images, tags, sheet),
+        _ => bail!("can only export to gif, apng or png: {}", path.display()),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Gif -
+//     GIF has no partial transparency: pixels under half opacity become
+//     transparent, everything else is made opaque.
+//     Up to 255 colours are kept as they are, with more than that
+//     each frame is quantised on its own.
+// -----------------------------------------------------------------------------
+fn gif(path: &Path, size: Size<i32>, images: &[Image], loops: u16) -> Result<()> {
+    let (width, height) = (size.width as u16, size.height as u16);
+    let opaque = |p: &Pixel| match p.a < 128 {
+        true => None,
+        false => Some(Pixel { a: 255, ..*p }),
+    };
+
+    // Index 0 is transparent
+    let mut palette = HashMap::new();
+    for pixel in images.iter().flat_map(|i| &i.pixels).filter_map(opaque) {
+        let index = palette.len() + 1;
+        palette.entry((pixel.r, pixel.g, pixel.b)).or_insert(index);
+    }
+
+    let exact = palette.len() < 256;
+    let global_palette = match exact {
+        true => {
+            let mut colors = vec![0u8; (palette.len() + 1) * 3];
+            for ((r, g, b), index) in &palette {
+                colors[index * 3..index * 3 + 3].copy_from_slice(&[*r, *g, *b]);
+            }
+            colors
+        }
+        false => vec![],
+    };
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = gif::Encoder::new(file, width, height, &global_palette)?;
+    // GIF counts the repeats after the first play
+    encoder.set_repeat(match loops {
+        0 => gif::Repeat::Infinite,
+        n => gif::Repeat::Finite(n - 1),
+    })?;
+
+    for image in images {
+        let mut frame = match exact {
+            true => {
+                let indices = image
+                    .pixels
+                    .iter()
+                    .map(|p| match opaque(p) {
+                        Some(p) => palette[&(p.r, p.g, p.b)] as u8,
+                        None => 0,
+                    })
+                    .collect::<Vec<_>>();
+                gif::Frame::from_indexed_pixels(width, height, &indices, Some(0))
+            }
+            false => {
+                let mut bytes = image
+                    .pixels
+                    .iter()
+                    .flat_map(|p| match opaque(p) {
+                        Some(p) => [p.r, p.g, p.b, 255],
+                        None => [0, 0, 0, 0],
+                    })
+                    .collect::<Vec<_>>();
+                gif::Frame::from_rgba_speed(width, height, &mut bytes, 10)
+            }
+        };
+
+        // In hundredths of a second, rounded
+        frame.delay = (image.duration.saturating_add(5) / 10).min(u16::MAX as u32) as u16;
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame)?;
+    }
+
+    Ok(())
+}
+
+fn apng(path: &Path, size: Size<i32>, images: &[Image], loops: u16) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.width as u32, size.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(images.len() as u32, loops as u32)?;
+
+    let mut writer = encoder.write_header()?;
+    for image in images {
+        let delay = image.duration.min(u16::MAX as u32) as u16;
+        writer.set_frame_delay(delay, 1000)?;
+        writer.set_dispose_op(png::DisposeOp::Background)?;
+
+        let bytes = image
+            .pixels
+            .iter()
+            .flat_map(|p| [p.r, p.g, p.b, p.a])
+            .collect::<Vec<_>>();
+        writer.write_image_data(&bytes)?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
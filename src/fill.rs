@@ -0,0 +1,95 @@
+use nightmaregl::{Position, Size};
+
+use crate::options::Connectivity;
+
+// -----------------------------------------------------------------------------
+//     - Flood -
+//     Every position connected to `start` for which `matches` is true.
+// -----------------------------------------------------------------------------
+pub fn flood(
+    start: Position<i32>,
+    size: Size<i32>,
+    connectivity: Connectivity,
+    matches: impl Fn(Position<i32>) -> bool,
+) -> Vec<Position<i32>> {
+    let inside = |pos: Position<i32>| {
+        pos.x >= 0 && pos.y >= 0 && pos.x < size.width && pos.y < size.height
+    };
+
+    if !inside(start) || !matches(start) {
+        return Vec::new();
+    }
+
+    let neighbours: &[(i32, i32)] = match connectivity {
+        Connectivity::Four => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+        Connectivity::Eight => &[
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ],
+    };
+
+    let index = |pos: Position<i32>| (pos.y * size.width + pos.x) as usize;
+    let mut visited = vec![false; (size.width * size.height) as usize];
+    let mut stack = vec![start];
+    let mut region = Vec::new();
+    visited[index(start)] = true;
+
+    while let Some(pos) = stack.pop() {
+        region.push(pos);
+
+        for (x, y) in neighbours {
+            let next = pos + Position::new(*x, *y);
+            if !inside(next) || visited[index(next)] {
+                continue;
+            }
+
+            visited[index(next)] = true;
+            if matches(next) {
+                stack.push(next);
+            }
+        }
+    }
+
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A diagonal wall from the top left to the bottom right
+    fn wall(pos: Position<i32>) -> bool {
+        pos.x != pos.y
+    }
+
+    #[test]
+    fn stops_at_the_wall() {
+        let size = Size::new(4, 4);
+        let region = flood(Position::new(3, 0), size, Connectivity::Four, wall);
+
+        // Everything above the diagonal
+        assert_eq!(region.len(), 6);
+        assert!(region.iter().all(|p| p.x > p.y));
+    }
+
+    #[test]
+    fn eight_connected_leaks_through_diagonals() {
+        let size = Size::new(4, 4);
+        let region = flood(Position::new(3, 0), size, Connectivity::Eight, wall);
+        assert_eq!(region.len(), 12);
+    }
+
+    #[test]
+    fn start_outside_or_not_matching() {
+        let size = Size::new(4, 4);
+        assert!(flood(Position::new(4, 0), size, Connectivity::Four, |_| true).is_empty());
+        assert!(flood(Position::new(1, 1), size, Connectivity::Four, wall).is_empty());
+        assert_eq!(flood(Position::new(0, 3), size, Connectivity::Four, |_| true).len(), 16);
+    }
+}
@@ -0,0 +1,108 @@
+use nightmaregl::{Pixel, Position};
+
+use crate::register::Register;
+
+pub const MAX_SCALE: i32 = 16;
+
+// Floating pixels are never scaled past this many pixels on either side
+const MAX_SIDE: i32 = 4096;
+
+// Rotating by any angle works on a copy this many times as large
+const ROTATE_UPSCALE: i32 = 8;
+
+// -----------------------------------------------------------------------------
+//     - Floating -
+//     Pixels above the current layer that can be moved and transformed
+//     before they are committed (Enter) or thrown away (Esc).
+//     `lifted` is what the layer held where the pixels were lifted from,
+//     so it can be put back.
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct Floating {
+    pub position: Position<i32>,
+    pub lifted: Vec<(Position<i32>, Pixel)>,
+    buffer: Register,
+}
+
+impl Floating {
+    pub fn new(position: Position<i32>, buffer: Register) -> Self {
+        Self::lifted(position, buffer, Vec::new())
+    }
+
+    pub fn lifted(position: Position<i32>, buffer: Register, lifted: Vec<(Position<i32>, Pixel)>) -> Self {
+        Self {
+            position,
+            lifted,
+            buffer,
+        }
+    }
+
+    pub fn pixels(&self) -> Vec<(Position<i32>, Pixel)> {
+        self.buffer.pixels_at(self.position)
+    }
+
+    pub fn flip(&mut self, vertical: bool) {
+        self.buffer = self.buffer.flipped(vertical);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Rotate -
+    //     Rotate a quarter turn around the centre
+    // -----------------------------------------------------------------------------
+    pub fn rotate(&mut self, clockwise: bool) {
+        let before = self.buffer.size;
+        self.buffer = self.buffer.rotated(clockwise);
+        let after = self.buffer.size;
+        self.position += Position::new(
+            (before.width - after.width) / 2,
+            (before.height - after.height) / 2,
+        );
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Rotate by -
+    //     Rotate by any angle, keeping the centre in place.
+    //     Returns false (and leaves the pixels alone) if the pixels,
+    //     either upscaled on the way or rotated, would be too large.
+    // -----------------------------------------------------------------------------
+    pub fn rotate_by(&mut self, degrees: f32) -> bool {
+        let before = self.buffer.size;
+        let rotated = self.buffer.rotated_size(degrees);
+        let upscaled = |side: i32| side <= MAX_SIDE / ROTATE_UPSCALE;
+
+        if !upscaled(before.width) || !upscaled(before.height) {
+            return false;
+        }
+
+        if rotated.width > MAX_SIDE || rotated.height > MAX_SIDE {
+            return false;
+        }
+
+        self.buffer = self.buffer.rotated_by(degrees);
+        let after = self.buffer.size;
+        self.position += Position::new(
+            (before.width - after.width) / 2,
+            (before.height - after.height) / 2,
+        );
+        true
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Scale -
+    //     Returns false (and leaves the pixels alone) if the result would be too large
+    // -----------------------------------------------------------------------------
+    pub fn scale(&mut self, factor: i32) -> bool {
+        let size = self.buffer.size;
+        let fits = |side: i32| match side.checked_mul(factor) {
+            Some(side) => side <= MAX_SIDE,
+            None => false,
+        };
+
+        if !fits(size.width) || !fits(size.height) {
+            return false;
+        }
+
+        self.buffer = self.buffer.scaled(factor);
+        true
+    }
+}
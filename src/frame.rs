@@ -0,0 +1,139 @@
+use anyhow::{bail, Result};
+
+use crate::layer::Layer;
+
+pub const DEFAULT_DURATION: u32 = 100;
+
+// -----------------------------------------------------------------------------
+//     - Frame -
+//     One step of an animation: a layer stack, shown for `duration`
+//     milliseconds. Every frame has the same layers, in the same order.
+// -----------------------------------------------------------------------------
+pub struct Frame {
+    pub layers: Vec<Layer>,
+    pub duration: u32,
+}
+
+impl Frame {
+    pub fn new(layers: Vec<Layer>) -> Self {
+        Self {
+            layers,
+            duration: DEFAULT_DURATION,
+        }
+    }
+
+    pub fn duplicate(&self) -> Self {
+        Self {
+            layers: self.layers.iter().map(Layer::duplicate).collect(),
+            duration: self.duration,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Direction -
+//     How a tagged animation plays
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+impl Direction {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "forward" => Ok(Direction::Forward),
+            "reverse" => Ok(Direction::Reverse),
+            "pingpong" => Ok(Direction::PingPong),
+            _ => bail!("direction is one of forward, reverse, pingpong"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Forward => "forward",
+            Direction::Reverse => "reverse",
+            Direction::PingPong => "pingpong",
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Tag -
+//     A named animation: the frames `from` to `to` (both inclusive)
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: Direction,
+}
+
+// -----------------------------------------------------------------------------
+//     - Frame inserted / removed -
+//     Keep the tags on the same frames when frames are added or removed.
+//     A frame added inside a tag makes it longer.
+// -----------------------------------------------------------------------------
+pub fn frame_inserted(tags: &mut Vec<Tag>, at: usize) {
+    for tag in tags.iter_mut() {
+        if tag.from >= at {
+            tag.from += 1;
+        }
+        if tag.to >= at {
+            tag.to += 1;
+        }
+    }
+}
+
+pub fn frame_removed(tags: &mut Vec<Tag>, at: usize) {
+    tags.retain(|tag| !(tag.from == at && tag.to == at));
+    for tag in tags.iter_mut() {
+        if tag.from > at {
+            tag.from -= 1;
+        }
+        if tag.to >= at {
+            tag.to -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, from: usize, to: usize) -> Tag {
+        Tag {
+            name: name.to_string(),
+            from,
+            to,
+            direction: Direction::Forward,
+        }
+    }
+
+    fn ranges(tags: &[Tag]) -> Vec<(&str, usize, usize)> {
+        tags.iter().map(|t| (t.name.as_str(), t.from, t.to)).collect()
+    }
+
+    #[test]
+    fn inserted() {
+        let mut tags = vec![tag("before", 0, 1), tag("around", 1, 3), tag("after", 3, 4)];
+        frame_inserted(&mut tags, 2);
+        assert_eq!(
+            ranges(&tags),
+            vec![("before", 0, 1), ("around", 1, 4), ("after", 4, 5)]
+        );
+    }
+
+    #[test]
+    fn removed() {
+        let mut tags = vec![tag("before", 0, 1), tag("around", 1, 3), tag("only", 2, 2), tag("after", 3, 4)];
+        frame_removed(&mut tags, 2);
+        assert_eq!(
+            ranges(&tags),
+            vec![("before", 0, 1), ("around", 1, 2), ("after", 2, 3)]
+        );
+    }
+}
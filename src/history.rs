@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use nightmaregl::{Pixel, Position};
+
+// -----------------------------------------------------------------------------
+//     - Change -
+//     A single pixel on a single layer of a single frame
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct Change {
+    pub frame: usize,
+    pub layer: usize,
+    pub position: Position<i32>,
+    pub before: Pixel,
+    pub after: Pixel,
+}
+
+// -----------------------------------------------------------------------------
+//     - Link change -
+//     Linking or unlinking a cel
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct LinkChange {
+    pub frame: usize,
+    pub layer: usize,
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+}
+
+// -----------------------------------------------------------------------------
+//     - Edit -
+//     Everything that is undone / redone in one step.
+//     The first change is on the frame that was edited, any others
+//     on different frames are from linked cels.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct Edit {
+    pub changes: Vec<Change>,
+    pub links: Vec<LinkChange>,
+    // The first change to each pixel, by (frame, layer, x, y).
+    // Only needed while the edit is being made, so it is dropped
+    // once the edit is in the history.
+    index: HashMap<(usize, usize, i32, i32), usize>,
+}
+
+impl Edit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(
+        &mut self,
+        (frame, layer): (usize, usize),
+        position: Position<i32>,
+        before: Pixel,
+        after: Pixel,
+    ) {
+        if before == after {
+            return;
+        }
+
+        let key = (frame, layer, position.x, position.y);
+        self.index.entry(key).or_insert(self.changes.len());
+        self.changes.push(Change {
+            frame,
+            layer,
+            position,
+            before,
+            after,
+        });
+    }
+
+    pub fn link(&mut self, (frame, layer): (usize, usize), before: Option<usize>, after: Option<usize>) {
+        if before == after {
+            return;
+        }
+
+        self.links.push(LinkChange {
+            frame,
+            layer,
+            before,
+            after,
+        });
+    }
+
+    // The frame that was edited
+    pub fn frame(&self) -> Option<usize> {
+        let link = self.links.first().map(|l| l.frame);
+        self.changes.first().map(|c| c.frame).or(link)
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Before -
+    //     The pixel as it was before this edit touched it
+    // -----------------------------------------------------------------------------
+    pub fn before(&self, (frame, layer): (usize, usize), position: Position<i32>) -> Option<Pixel> {
+        if self.index.is_empty() {
+            return self
+                .changes
+                .iter()
+                .find(|c| c.frame == frame && c.layer == layer && c.position == position)
+                .map(|c| c.before);
+        }
+
+        let key = (frame, layer, position.x, position.y);
+        self.index.get(&key).map(|i| self.changes[*i].before)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.links.is_empty()
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - History -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mut edit: Edit) {
+        if edit.is_empty() {
+            return;
+        }
+
+        edit.index = HashMap::new();
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Undo / Redo -
+    //     The edit is moved to the other stack, and a reference to it is returned
+    //     so the caller can apply the changes.
+    // -----------------------------------------------------------------------------
+    pub fn undo(&mut self) -> Option<&Edit> {
+        let edit = self.undo.pop()?;
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    pub fn redo(&mut self) -> Option<&Edit> {
+        let edit = self.redo.pop()?;
+        self.undo.push(edit);
+        self.undo.last()
+    }
+
+    // Every edit that can be undone, oldest first
+    pub fn edits(&self) -> &[Edit] {
+        &self.undo
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Remap frames -
+    //     Keep the history pointing at the right frames when frames are
+    //     added or removed. Changes to a frame that is gone (`None`) are dropped.
+    // -----------------------------------------------------------------------------
+    pub fn remap_frames(&mut self, f: impl Fn(usize) -> Option<usize>) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            edit.changes = edit
+                .changes
+                .drain(..)
+                .filter_map(|c| f(c.frame).map(|frame| Change { frame, ..c }))
+                .collect();
+            edit.links = edit
+                .links
+                .drain(..)
+                .filter_map(|l| f(l.frame).map(|frame| LinkChange { frame, ..l }))
+                .collect();
+        }
+
+        self.undo.retain(|e| !e.is_empty());
+        self.redo.retain(|e| !e.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(r: u8) -> Pixel {
+        Pixel { r, g: 0, b: 0, a: 255 }
+    }
+
+    fn edit(frame: usize) -> Edit {
+        let mut edit = Edit::new();
+        edit.push((frame, 0), Position::new(0, 0), pixel(1), pixel(2));
+        edit
+    }
+
+    #[test]
+    fn before_is_the_first_change() {
+        let mut edit = Edit::new();
+        let pos = Position::new(3, 4);
+        edit.push((0, 0), pos, pixel(1), pixel(2));
+        edit.push((0, 0), pos, pixel(2), pixel(3));
+        edit.push((1, 0), pos, pixel(7), pixel(8));
+
+        assert_eq!(edit.before((0, 0), pos), Some(pixel(1)));
+        assert_eq!(edit.before((1, 0), pos), Some(pixel(7)));
+        assert_eq!(edit.before((0, 1), pos), None);
+    }
+
+    #[test]
+    fn before_without_the_index() {
+        let mut history = History::new();
+        let mut edit = edit(0);
+        edit.push((0, 0), Position::new(0, 0), pixel(2), pixel(3));
+        history.push(edit);
+
+        let edit = &history.edits()[0];
+        assert!(edit.index.is_empty());
+        assert_eq!(edit.before((0, 0), Position::new(0, 0)), Some(pixel(1)));
+        assert_eq!(edit.before((0, 0), Position::new(1, 0)), None);
+    }
+
+    #[test]
+    fn unchanged_pixels_are_not_recorded() {
+        let mut edit = Edit::new();
+        edit.push((0, 0), Position::new(0, 0), pixel(1), pixel(1));
+        assert!(edit.is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new();
+        history.push(edit(0));
+        history.push(edit(1));
+
+        assert_eq!(history.undo().and_then(Edit::frame), Some(1));
+        assert_eq!(history.undo().and_then(Edit::frame), Some(0));
+        assert!(history.undo().is_none());
+        assert_eq!(history.redo().and_then(Edit::frame), Some(0));
+
+        // A new edit drops what could be redone
+        history.push(edit(2));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn remap_frames() {
+        let mut history = History::new();
+        history.push(edit(0));
+        history.push(edit(1));
+        history.push(edit(2));
+
+        // Remove frame 1
+        history.remap_frames(|f| match f {
+            1 => None,
+            f if f > 1 => Some(f - 1),
+            f => Some(f),
+        });
+
+        let frames = history.edits().iter().map(Edit::frame).collect::<Vec<_>>();
+        assert_eq!(frames, vec![Some(0), Some(1)]);
+        assert_eq!(history.edits()[1].before((1, 0), Position::new(0, 0)), Some(pixel(1)));
+
+        // Insert a frame at 1
+        history.remap_frames(|f| match f >= 1 {
+            true => Some(f + 1),
+            false => Some(f),
+        });
+
+        let frames = history.edits().iter().map(Edit::frame).collect::<Vec<_>>();
+        assert_eq!(frames, vec![Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn link_changes() {
+        let mut edit = Edit::new();
+        edit.link((0, 0), Some(1), Some(1));
+        assert!(edit.is_empty());
+
+        // An edit with only link changes is still on a frame
+        edit.link((2, 0), None, Some(1));
+        edit.link((3, 0), None, Some(1));
+        assert_eq!(edit.frame(), Some(2));
+
+        let mut history = History::new();
+        history.push(edit);
+        history.remap_frames(|f| match f {
+            2 => None,
+            f => Some(f - 1),
+        });
+
+        let links = &history.edits()[0].links;
+        assert_eq!(links.len(), 1);
+        assert_eq!((links[0].frame, links[0].before, links[0].after), (2, None, Some(1)));
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use nightmaregl::{Pixel, Size};
+
+// -----------------------------------------------------------------------------
+//     - Read png -
+//     Any png, as 8 bit RGBA pixels in reading order
+// -----------------------------------------------------------------------------
+pub fn read_png(path: &Path) -> Result<(Size<i32>, Vec<Pixel>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let bytes = &buf[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => bytes
+            .chunks(4)
+            .map(|c| Pixel { r: c[0], g: c[1], b: c[2], a: c[3] })
+            .collect(),
+        png::ColorType::Rgb => bytes
+            .chunks(3)
+            .map(|c| Pixel { r: c[0], g: c[1], b: c[2], a: 255 })
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks(2)
+            .map(|c| Pixel { r: c[0], g: c[0], b: c[0], a: c[1] })
+            .collect(),
+        png::ColorType::Grayscale => bytes
+            .iter()
+            .map(|c| Pixel { r: *c, g: *c, b: *c, a: 255 })
+            .collect(),
+        png::ColorType::Indexed => bail!("unexpanded palette in {}", path.display()),
+    };
+
+    Ok((Size::new(info.width as i32, info.height as i32), pixels))
+}
+
+// -----------------------------------------------------------------------------
+//     - Slice -
+//     Cut a sheet into cells, left to right and top to bottom.
+//     `margin` is the space around the grid, `spacing` the space between cells.
+//     Cells without a single visible pixel are skipped.
+// -----------------------------------------------------------------------------
+pub fn slice(
+    (size, pixels): (Size<i32>, Vec<Pixel>),
+    cell: Size<i32>,
+    margin: i32,
+    spacing: i32,
+) -> Vec<Vec<Pixel>> {
+    let columns = (size.width - margin * 2 + spacing) / (cell.width + spacing);
+    let rows = (size.height - margin * 2 + spacing) / (cell.height + spacing);
+
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |col| (col, row)))
+        .map(|(col, row)| {
+            let x = margin + col * (cell.width + spacing);
+            let y = margin + row * (cell.height + spacing);
+            (0..cell.height)
+                .flat_map(|cy| (0..cell.width).map(move |cx| (x + cx, y + cy)))
+                .map(|(x, y)| pixels[(y * size.width + x) as usize])
+                .collect::<Vec<_>>()
+        })
+        .filter(|cell| cell.iter().any(|p| p.a > 0))
+        .collect()
+}
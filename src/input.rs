@@ -16,6 +16,21 @@ pub enum Action {
     Right,
     Up,
     Down,
+    Undo,
+    Redo,
+    SearchNext,
+    SearchPrev,
+    SearchUnderCursor,
+    Fill,
+    FillGlobal,
+    Anchor,
+    Commit,
+    Pen,
+    Yank,
+    Delete,
+    Paste,
+    RotateCw,
+    RotateCcw,
     Draw,
     CommandInput,
     CloseCommandInput,
@@ -29,6 +44,21 @@ impl Action {
             "right" => Action::Right,
             "up" => Action::Up,
             "down" => Action::Down,
+            "undo" => Action::Undo,
+            "redo" => Action::Redo,
+            "search_next" => Action::SearchNext,
+            "search_prev" => Action::SearchPrev,
+            "search_under_cursor" => Action::SearchUnderCursor,
+            "fill" => Action::Fill,
+            "fill_global" => Action::FillGlobal,
+            "anchor" => Action::Anchor,
+            "commit" => Action::Commit,
+            "pen" => Action::Pen,
+            "yank" => Action::Yank,
+            "delete" => Action::Delete,
+            "paste" => Action::Paste,
+            "rotate_cw" => Action::RotateCw,
+            "rotate_ccw" => Action::RotateCcw,
             _ => Action::Noop,
         }
     }
@@ -125,9 +155,9 @@ impl InputMap {
 
     fn map_input(&self, c: char, mode: Mode, ctrl: bool) -> Option<Action> {
         match mode {
-            Mode::Insert => self.insert.map_input(c, ctrl),
+            Mode::Insert(_) => self.insert.map_input(c, ctrl),
             Mode::Normal => self.normal.map_input(c, ctrl),
-            Mode::Visual => self.visual.map_input(c, ctrl),
+            Mode::Visual(_) => self.visual.map_input(c, ctrl),
             Mode::Command => return None,
         }
     }
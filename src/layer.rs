@@ -0,0 +1,138 @@
+use nightmaregl::{Pixel, Pixels, Position, Size, Texture};
+
+// -----------------------------------------------------------------------------
+//     - Layer -
+//     Keeps a copy of the pixels on the CPU side so they can be read back
+//     (e.g for colour substitution and undo), and a texture for rendering.
+//     Layers (cels) in different frames with the same `link` are kept identical.
+//     `blank` is what the layer was filled with when it was created:
+//     a pixel that still holds it has not been painted.
+// -----------------------------------------------------------------------------
+pub struct Layer {
+    pub texture: Texture<i32>,
+    pub alpha_lock: bool,
+    pub link: Option<usize>,
+    pixels: Vec<Pixel>,
+    size: Size<i32>,
+    blank: Pixel,
+}
+
+impl Layer {
+    pub fn new(size: Size<i32>, fill: Pixel) -> Self {
+        let pixels = Pixels::from_pixel(fill, size.cast());
+        let texture = Texture::default_with_data(size, pixels.as_bytes());
+
+        Self {
+            texture,
+            alpha_lock: false,
+            link: None,
+            pixels: vec![fill; (size.width * size.height) as usize],
+            size,
+            blank: fill,
+        }
+    }
+
+    pub fn from_pixels(size: Size<i32>, pixels: Vec<Pixel>) -> Self {
+        let mut layer = Self::new(size, Pixel::transparent());
+        layer.pixels = pixels;
+        layer.sync();
+        layer
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Duplicate -
+    //     A copy with its own texture
+    // -----------------------------------------------------------------------------
+    pub fn duplicate(&self) -> Self {
+        let mut layer = Self::new(self.size, Pixel::transparent());
+        layer.alpha_lock = self.alpha_lock;
+        layer.link = self.link;
+        layer.blank = self.blank;
+        layer.pixels = self.pixels.clone();
+        layer.sync();
+        layer
+    }
+
+    pub fn size(&self) -> Size<i32> {
+        self.size
+    }
+
+    pub fn blank(&self) -> Pixel {
+        self.blank
+    }
+
+    pub fn contains(&self, pos: Position<i32>) -> bool {
+        pos.x >= 0 && pos.y >= 0 && pos.x < self.size.width && pos.y < self.size.height
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Positions -
+    //     Every position in the layer, in reading order
+    // -----------------------------------------------------------------------------
+    pub fn positions(&self) -> impl Iterator<Item = Position<i32>> {
+        let size = self.size;
+        (0..size.height).flat_map(move |y| (0..size.width).map(move |x| Position::new(x, y)))
+    }
+
+    fn index(&self, pos: Position<i32>) -> Option<usize> {
+        match self.contains(pos) {
+            true => Some((pos.y * self.size.width + pos.x) as usize),
+            false => None,
+        }
+    }
+
+    pub fn pixel(&self, pos: Position<i32>) -> Option<Pixel> {
+        self.index(pos).map(|i| self.pixels[i])
+    }
+
+    pub fn painted(&self, pos: Position<i32>) -> bool {
+        match self.pixel(pos) {
+            Some(pixel) => pixel.a > 0 && pixel != self.blank,
+            None => false,
+        }
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn fill(&mut self, pixel: Pixel) {
+        self.pixels.iter_mut().for_each(|p| *p = pixel);
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Set pixel -
+    //     Only changes the CPU side. Call `sync` once done
+    //     to upload the changes to the texture.
+    //     Returns the previous pixel.
+    // -----------------------------------------------------------------------------
+    pub fn set_pixel(&mut self, pos: Position<i32>, pixel: Pixel) -> Option<Pixel> {
+        let index = self.index(pos)?;
+        let prev = self.pixels[index];
+        self.pixels[index] = pixel;
+        Some(prev)
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Write pixel -
+    //     Set the pixel and write it straight to the texture.
+    // -----------------------------------------------------------------------------
+    pub fn write_pixel(&mut self, pos: Position<i32>, pixel: Pixel) -> Option<Pixel> {
+        let prev = self.set_pixel(pos, pixel)?;
+        let pix_buf = Pixels::from_pixel(pixel, Size::new(1, 1));
+        self.texture.write_region(pos, Size::new(1, 1), pix_buf.as_bytes());
+        Some(prev)
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Sync -
+    //     Upload the entire layer to the texture
+    // -----------------------------------------------------------------------------
+    pub fn sync(&self) {
+        self.texture.write_region(Position::zero(), self.size, &self.as_bytes());
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| vec![p.r, p.g, p.b, p.a]).collect()
+    }
+}
@@ -5,12 +5,31 @@ use nightmaregl::events::{Event, LoopAction};
 use nightmaregl::{Color, Context, Size};
 
 mod application;
+mod aseprite;
+mod brush;
 mod canvas;
+mod color;
 mod commands;
+mod dither;
+mod export;
+mod fill;
+mod floating;
+mod frame;
+mod history;
+mod import;
 mod input;
+mod layer;
+mod mirror;
+mod options;
+mod playback;
+mod register;
+mod selection;
+mod shapes;
+mod sheet;
+mod timelapse;
 
 use commands::Command;
-pub use application::{App, Mode};
+pub use application::{App, Insert, Mode};
 
 fn run() -> Result<()> {
     let (eventloop, mut context) = Context::builder("Mixel: the modal pixel editor")
@@ -40,6 +59,9 @@ fn run() -> Result<()> {
             Event::Key { key, state } => app.update_modifier(key, state),
 
             Event::Draw(dt) => {
+                app.update(now.elapsed());
+                now = Instant::now();
+
                 context.clear(Color::grey());
                 app.render(&mut context);
                 context.swap_buffers();
@@ -0,0 +1,76 @@
+use anyhow::{bail, Result};
+use nightmaregl::{Position, Size};
+
+// -----------------------------------------------------------------------------
+//     - Mirror -
+//     :set mirror=x|y|xy|off
+//     :set mirroraxis=15.5,15.5
+//
+//     The axes are stored doubled so they can sit between two pixels:
+//     a pixel at `x` is mirrored to `axis_x - x`.
+//     Without an axis set the centre of the canvas is used.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Mirror {
+    pub x: bool,
+    pub y: bool,
+    axis_x: Option<i32>,
+    axis_y: Option<i32>,
+}
+
+impl Mirror {
+    pub fn set_mode(&mut self, value: &str) -> Result<()> {
+        let (x, y) = match value {
+            "x" => (true, false),
+            "y" => (false, true),
+            "xy" => (true, true),
+            "off" => (false, false),
+            _ => bail!("mirror is one of x, y, xy or off"),
+        };
+
+        self.x = x;
+        self.y = y;
+        Ok(())
+    }
+
+    pub fn set_axis(&mut self, value: &str) -> Result<()> {
+        let mut parts = value.split(',');
+        let mut axis = || -> Result<Option<i32>> {
+            match parts.next().map(str::trim) {
+                None | Some("") => Ok(None),
+                Some(n) => Ok(Some((n.parse::<f32>()? * 2.0).round() as i32)),
+            }
+        };
+
+        self.axis_x = axis()?;
+        self.axis_y = axis()?;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Positions -
+    //     The position itself followed by all mirrored positions
+    // -----------------------------------------------------------------------------
+    pub fn positions(&self, pos: Position<i32>, size: Size<i32>) -> Vec<Position<i32>> {
+        let axis_x = self.axis_x.unwrap_or(size.width - 1);
+        let axis_y = self.axis_y.unwrap_or(size.height - 1);
+        let flip_x = |p: Position<i32>| Position::new(axis_x - p.x, p.y);
+        let flip_y = |p: Position<i32>| Position::new(p.x, axis_y - p.y);
+
+        let mut positions = vec![pos];
+
+        if self.x {
+            positions.push(flip_x(pos));
+        }
+
+        if self.y {
+            positions.push(flip_y(pos));
+        }
+
+        if self.x && self.y {
+            positions.push(flip_x(flip_y(pos)));
+        }
+
+        positions
+    }
+}
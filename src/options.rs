@@ -0,0 +1,180 @@
+use anyhow::{anyhow, bail, Result};
+use nightmaregl::Pixel;
+
+use crate::brush::Brush;
+use crate::color::parse_hex;
+use crate::dither::Pattern;
+use crate::mirror::Mirror;
+use crate::shapes::Shape;
+use crate::sheet::SheetOptions;
+
+// -----------------------------------------------------------------------------
+//     - Connectivity -
+//     Four: only horizontal and vertical neighbours
+//     Eight: diagonal neighbours as well
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+// -----------------------------------------------------------------------------
+//     - Sample -
+//     Where a fill reads colours from.
+//     Fills always write to the current layer.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Sample {
+    Layer,
+    Composite,
+}
+
+// -----------------------------------------------------------------------------
+//     - Options -
+//     Set with `:set <key>=<value>`
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct Options {
+    pub fg: Pixel,
+    pub bg: Pixel,
+    pub tolerance: u8,
+    pub connectivity: Connectivity,
+    pub sample: Sample,
+    pub shape: Shape,
+    pub brush: Brush,
+    pub recolor: bool,
+    pub mirror: Mirror,
+    pub pattern: Pattern,
+    pub opacity: u8,
+    pub pixel_perfect: bool,
+    pub onion: usize,
+    pub onion_prev: Pixel,
+    pub onion_next: Pixel,
+    pub ping_pong: bool,
+    pub loop_range: Option<(usize, usize)>,
+    // Times an exported animation plays in total, 0 for forever
+    pub loops: u16,
+    pub sheet: SheetOptions,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            fg: Pixel::white(),
+            bg: Pixel::black(),
+            tolerance: 0,
+            connectivity: Connectivity::Four,
+            sample: Sample::Layer,
+            shape: Shape::Line,
+            brush: Brush::square(1),
+            recolor: false,
+            mirror: Mirror::default(),
+            pattern: Pattern::Solid,
+            opacity: 100,
+            pixel_perfect: false,
+            onion: 0,
+            onion_prev: Pixel {
+                r: 255,
+                g: 64,
+                b: 64,
+                a: 128,
+            },
+            onion_next: Pixel {
+                r: 64,
+                g: 160,
+                b: 255,
+                a: 128,
+            },
+            ping_pong: false,
+            loop_range: None,
+            loops: 0,
+            sheet: SheetOptions::default(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "fg" => self.fg = parse_hex(value).ok_or_else(|| anyhow!("invalid colour: {}", value))?,
+            "bg" => self.bg = parse_hex(value).ok_or_else(|| anyhow!("invalid colour: {}", value))?,
+            "tolerance" => self.tolerance = value.parse()?,
+            "connectivity" => {
+                self.connectivity = match value {
+                    "4" => Connectivity::Four,
+                    "8" => Connectivity::Eight,
+                    _ => bail!("connectivity is either 4 or 8"),
+                }
+            }
+            "sample" => {
+                self.sample = match value {
+                    "layer" => Sample::Layer,
+                    "composite" => Sample::Composite,
+                    _ => bail!("sample is either layer or composite"),
+                }
+            }
+            "shape" => {
+                self.shape = Shape::from_str(value).ok_or_else(|| {
+                    anyhow!("shape is one of line, rect, rectfill, ellipse, ellipsefill")
+                })?
+            }
+            "recolor" => self.recolor = parse_bool(value)?,
+            "pixelperfect" => self.pixel_perfect = parse_bool(value)?,
+            "mirror" => self.mirror.set_mode(value)?,
+            "mirroraxis" => self.mirror.set_axis(value)?,
+            "pattern" => self.pattern = Pattern::from_str(value)?,
+            "opacity" => {
+                self.opacity = match value.parse()? {
+                    opacity @ 0..=100 => opacity,
+                    _ => bail!("opacity is between 0 and 100"),
+                }
+            }
+            "onion" => self.onion = value.parse()?,
+            "onionprev" => self.onion_prev = parse_hex(value).ok_or_else(|| anyhow!("invalid colour: {}", value))?,
+            "onionnext" => self.onion_next = parse_hex(value).ok_or_else(|| anyhow!("invalid colour: {}", value))?,
+            "pingpong" => self.ping_pong = parse_bool(value)?,
+            "looprange" => self.loop_range = parse_range(value)?,
+            "loops" => self.loops = value.parse()?,
+            "sheet" => self.sheet.set_layout(value)?,
+            "sheetjson" => self.sheet.set_json(value)?,
+            "columns" => self.sheet.columns = value.parse()?,
+            "padding" => self.sheet.padding = value.parse::<u16>()? as i32,
+            "extrude" => self.sheet.extrude = value.parse::<u16>()? as i32,
+            _ => bail!("unknown option: {}", key),
+        }
+
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Bool -
+//     `:set recolor` is the same as `:set recolor=on`
+// -----------------------------------------------------------------------------
+pub fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "" | "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => bail!("expected on or off, got {}", value),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Range -
+//     Frames `first-last`, counting from 1. Empty or `all` for every frame.
+// -----------------------------------------------------------------------------
+pub fn parse_range(value: &str) -> Result<Option<(usize, usize)>> {
+    if value.is_empty() || value == "all" {
+        return Ok(None);
+    }
+
+    let (first, last) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow!("expected a range like 1-4, got {}", value))?;
+    let (first, last): (usize, usize) = (first.parse()?, last.parse()?);
+
+    if first == 0 || first > last {
+        bail!("invalid range: {}", value);
+    }
+
+    Ok(Some((first, last)))
+}
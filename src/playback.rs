@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use crate::frame::Direction;
+
+// -----------------------------------------------------------------------------
+//     - Playback -
+//     Loops over a range of frames (both inclusive) at their durations.
+//     Only decides which frame is shown, the current frame is left alone.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct Playback {
+    pub frame: usize,
+    first: usize,
+    last: usize,
+    direction: Direction,
+    elapsed: Duration,
+    reverse: bool,
+}
+
+impl Playback {
+    pub fn new(first: usize, last: usize, direction: Direction) -> Self {
+        let reverse = direction == Direction::Reverse;
+        Self {
+            frame: match reverse {
+                true => last,
+                false => first,
+            },
+            first,
+            last,
+            direction,
+            elapsed: Duration::default(),
+            reverse,
+        }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Advance -
+    //     Move on as many frames as fit in the time that passed.
+    //     Ping-pong goes back and forth instead of starting over.
+    // -----------------------------------------------------------------------------
+    pub fn advance(&mut self, dt: Duration, durations: &[u32]) {
+        self.elapsed += dt;
+
+        loop {
+            let duration = Duration::from_millis(durations[self.frame].max(1) as u64);
+            if self.elapsed < duration {
+                break;
+            }
+
+            self.elapsed -= duration;
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        if self.first == self.last {
+            return;
+        }
+
+        let ping_pong = self.direction == Direction::PingPong;
+        match (self.reverse, ping_pong) {
+            (false, _) if self.frame < self.last => self.frame += 1,
+            (false, true) => {
+                self.reverse = true;
+                self.frame -= 1;
+            }
+            (false, false) => self.frame = self.first,
+            (true, _) if self.frame > self.first => self.frame -= 1,
+            (true, true) => {
+                self.reverse = false;
+                self.frame += 1;
+            }
+            (true, false) => self.frame = self.last,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every frame shown for 100ms, one step at a time
+    fn frames(mut playback: Playback, steps: usize) -> Vec<usize> {
+        let durations = [100; 6];
+        (0..steps)
+            .map(|_| {
+                playback.advance(Duration::from_millis(100), &durations);
+                playback.frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forward_loops_over_the_range() {
+        let playback = Playback::new(1, 3, Direction::Forward);
+        assert_eq!(playback.frame, 1);
+        assert_eq!(frames(playback, 5), vec![2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn reverse_starts_at_the_end() {
+        let playback = Playback::new(1, 3, Direction::Reverse);
+        assert_eq!(playback.frame, 3);
+        assert_eq!(frames(playback, 4), vec![2, 1, 3, 2]);
+    }
+
+    #[test]
+    fn ping_pong() {
+        let playback = Playback::new(0, 2, Direction::PingPong);
+        assert_eq!(frames(playback, 6), vec![1, 2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn skips_frames_that_fit_in_the_time() {
+        let mut playback = Playback::new(0, 3, Direction::Forward);
+        let durations = [100, 50, 50, 100];
+
+        playback.advance(Duration::from_millis(99), &durations);
+        assert_eq!(playback.frame, 0);
+        playback.advance(Duration::from_millis(101), &durations);
+        assert_eq!(playback.frame, 3);
+    }
+
+    #[test]
+    fn single_frame() {
+        let playback = Playback::new(2, 2, Direction::PingPong);
+        assert_eq!(frames(playback, 3), vec![2, 2, 2]);
+    }
+}
@@ -0,0 +1,214 @@
+use nightmaregl::{Pixel, Position, Size};
+
+// -----------------------------------------------------------------------------
+//     - Register -
+//     Yanked (or deleted) pixels, in reading order.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub size: Size<i32>,
+    pub pixels: Vec<Pixel>,
+}
+
+impl Register {
+    // -----------------------------------------------------------------------------
+    //     - Pixels at -
+    //     The pixels with their positions when the top left corner is at `at`
+    // -----------------------------------------------------------------------------
+    pub fn pixels_at(&self, at: Position<i32>) -> Vec<(Position<i32>, Pixel)> {
+        let width = self.size.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let i = i as i32;
+                (at + Position::new(i % width, i / width), *p)
+            })
+            .collect()
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> Pixel {
+        self.pixels[(y * self.size.width + x) as usize]
+    }
+
+    // Edge pixels repeat outwards
+    fn clamped(&self, x: i32, y: i32) -> Pixel {
+        let x = x.clamp(0, self.size.width - 1);
+        let y = y.clamp(0, self.size.height - 1);
+        self.pixel(x, y)
+    }
+
+    fn from_fn(size: Size<i32>, f: impl Fn(i32, i32) -> Pixel) -> Self {
+        let pixels = (0..size.height)
+            .flat_map(|y| (0..size.width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+
+        Self { size, pixels }
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Transforms -
+    // -----------------------------------------------------------------------------
+    pub fn flipped(&self, vertical: bool) -> Self {
+        let (width, height) = (self.size.width, self.size.height);
+        Self::from_fn(self.size, |x, y| match vertical {
+            true => self.pixel(x, height - 1 - y),
+            false => self.pixel(width - 1 - x, y),
+        })
+    }
+
+    pub fn rotated(&self, clockwise: bool) -> Self {
+        let (width, height) = (self.size.width, self.size.height);
+        Self::from_fn(Size::new(height, width), |x, y| match clockwise {
+            true => self.pixel(y, height - 1 - x),
+            false => self.pixel(width - 1 - y, x),
+        })
+    }
+
+    pub fn scaled(&self, factor: i32) -> Self {
+        let size = Size::new(self.size.width * factor, self.size.height * factor);
+        Self::from_fn(size, |x, y| self.pixel(x / factor, y / factor))
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Scale2x -
+    //     Double the size, rounding off diagonal edges (EPX / AdvMAME2x)
+    //     rather than turning every pixel into a 2x2 block.
+    // -----------------------------------------------------------------------------
+    fn scale2x(&self) -> Self {
+        let size = Size::new(self.size.width * 2, self.size.height * 2);
+        Self::from_fn(size, |x, y| {
+            let (sx, sy) = (x / 2, y / 2);
+            let p = self.pixel(sx, sy);
+            let up = self.clamped(sx, sy - 1);
+            let down = self.clamped(sx, sy + 1);
+            let left = self.clamped(sx - 1, sy);
+            let right = self.clamped(sx + 1, sy);
+
+            // The two neighbours touching this quarter of the pixel
+            let (v, h) = match (x % 2, y % 2) {
+                (0, 0) => (up, left),
+                (1, 0) => (up, right),
+                (0, _) => (down, left),
+                _ => (down, right),
+            };
+
+            // ... and the two on the opposite sides
+            let (v_far, h_far) = match (x % 2, y % 2) {
+                (0, 0) => (down, right),
+                (1, 0) => (down, left),
+                (0, _) => (up, right),
+                _ => (up, left),
+            };
+
+            match v == h && v != h_far && h != v_far {
+                true => v,
+                false => p,
+            }
+        })
+    }
+
+    // The size of the rectangle around the pixels after `rotated_by`
+    pub fn rotated_size(&self, degrees: f32) -> Size<i32> {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        Size::new(
+            ((width * cos.abs() + height * sin.abs()).round() as i32).max(1),
+            ((width * sin.abs() + height * cos.abs()).round() as i32).max(1),
+        )
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Rotated by -
+    //     Rotate clockwise by any angle, RotSprite style:
+    //     upscale 8x with Scale2x, rotate with nearest neighbour
+    //     and sample the centre of every 8x8 block on the way back down.
+    //     The result is the size of the rectangle around the rotated pixels.
+    // -----------------------------------------------------------------------------
+    pub fn rotated_by(&self, degrees: f32) -> Self {
+        const UPSCALE: f32 = 8.0;
+
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        let size = self.rotated_size(degrees);
+
+        let big = self.scale2x().scale2x().scale2x();
+
+        Self::from_fn(size, |x, y| {
+            let dx = x as f32 + 0.5 - size.width as f32 / 2.0;
+            let dy = y as f32 + 0.5 - size.height as f32 / 2.0;
+
+            let sx = cos * dx + sin * dy + width / 2.0;
+            let sy = -sin * dx + cos * dy + height / 2.0;
+
+            let inside = sx >= 0.0 && sy >= 0.0 && sx < width && sy < height;
+            match inside {
+                true => big.pixel((sx * UPSCALE) as i32, (sy * UPSCALE) as i32),
+                false => Pixel::transparent(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(r: u8) -> Pixel {
+        Pixel { r, g: 0, b: 0, a: 255 }
+    }
+
+    // Every pixel different, so Scale2x has no edges to round off
+    fn distinct(width: i32, height: i32) -> Register {
+        Register::from_fn(Size::new(width, height), |x, y| pixel((y * width + x) as u8))
+    }
+
+    #[test]
+    fn scale2x_rounds_off_diagonals() {
+        let (a, b) = (pixel(1), pixel(2));
+        let register = Register {
+            size: Size::new(2, 2),
+            pixels: vec![a, b, b, a],
+        };
+
+        let big = register.scale2x();
+        assert_eq!((big.size.width, big.size.height), (4, 4));
+        assert_eq!(big.pixel(0, 0), a);
+        assert_eq!(big.pixel(2, 0), b);
+
+        // The quarters of the two b pixels that touch the diagonal
+        assert_eq!(big.pixel(2, 1), a);
+        assert_eq!(big.pixel(1, 2), a);
+    }
+
+    #[test]
+    fn scale2x_without_edges_is_nearest_neighbour() {
+        let register = distinct(3, 2);
+        let big = register.scale2x();
+        assert_eq!(big.pixels, register.scaled(2).pixels);
+    }
+
+    #[test]
+    fn rotated_by_right_angles() {
+        let register = distinct(3, 2);
+
+        let same = register.rotated_by(0.0);
+        assert_eq!((same.size.width, same.size.height), (3, 2));
+        assert_eq!(same.pixels, register.pixels);
+
+        let quarter = register.rotated_by(90.0);
+        let expected = register.rotated(true);
+        assert_eq!((quarter.size.width, quarter.size.height), (2, 3));
+        assert_eq!(quarter.pixels, expected.pixels);
+    }
+
+    #[test]
+    fn rotated_size() {
+        let size = distinct(2, 2).rotated_size(45.0);
+        assert_eq!((size.width, size.height), (3, 3));
+
+        let size = distinct(4, 1).rotated_size(90.0);
+        assert_eq!((size.width, size.height), (1, 4));
+    }
+}
@@ -0,0 +1,234 @@
+use nightmaregl::{Position, Size};
+
+// -----------------------------------------------------------------------------
+//     - Visual -
+//     The kind of selection made in visual mode
+//     * Rect (v): the rectangle between the anchor and the cursor
+//     * Line (V): whole rows
+//     * Block (Ctrl-v): whole columns
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Visual {
+    Rect,
+    Line,
+    Block,
+}
+
+// -----------------------------------------------------------------------------
+//     - Selection -
+//     Between where visual mode was entered
+//     and the current cursor position (both inclusive).
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct Selection {
+    pub anchor: Position<i32>,
+    pub cursor: Position<i32>,
+    pub kind: Visual,
+    bounds: Size<i32>,
+}
+
+impl Selection {
+    pub fn new(anchor: Position<i32>, kind: Visual, bounds: Size<i32>) -> Self {
+        Self {
+            anchor,
+            cursor: anchor,
+            kind,
+            bounds,
+        }
+    }
+
+    pub fn origin(&self) -> Position<i32> {
+        let x = self.anchor.x.min(self.cursor.x);
+        let y = self.anchor.y.min(self.cursor.y);
+
+        match self.kind {
+            Visual::Rect => Position::new(x, y),
+            Visual::Line => Position::new(0, y),
+            Visual::Block => Position::new(x, 0),
+        }
+    }
+
+    pub fn size(&self) -> Size<i32> {
+        let width = (self.anchor.x - self.cursor.x).abs() + 1;
+        let height = (self.anchor.y - self.cursor.y).abs() + 1;
+
+        match self.kind {
+            Visual::Rect => Size::new(width, height),
+            Visual::Line => Size::new(self.bounds.width, height),
+            Visual::Block => Size::new(width, self.bounds.height),
+        }
+    }
+
+    pub fn positions(&self) -> Vec<Position<i32>> {
+        let origin = self.origin();
+        let size = self.size();
+        (0..size.height)
+            .flat_map(|y| (0..size.width).map(move |x| Position::new(origin.x + x, origin.y + y)))
+            .collect()
+    }
+
+    pub fn mask(&self) -> Mask {
+        Mask::from_positions(self.bounds, &self.positions())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Mask op -
+//     How a new mask is combined with the current one
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum MaskOp {
+    Replace,
+    Union,
+    Subtract,
+    Intersect,
+}
+
+// -----------------------------------------------------------------------------
+//     - Mask -
+//     A selection of any shape: one bool per pixel on the canvas.
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct Mask {
+    size: Size<i32>,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    pub fn new(size: Size<i32>) -> Self {
+        Self {
+            size,
+            bits: vec![false; (size.width * size.height) as usize],
+        }
+    }
+
+    pub fn from_positions(size: Size<i32>, positions: &[Position<i32>]) -> Self {
+        let mut mask = Self::new(size);
+        positions.iter().for_each(|pos| mask.set(*pos, true));
+        mask
+    }
+
+    fn index(&self, pos: Position<i32>) -> Option<usize> {
+        let inside = pos.x >= 0 && pos.y >= 0 && pos.x < self.size.width && pos.y < self.size.height;
+        match inside {
+            true => Some((pos.y * self.size.width + pos.x) as usize),
+            false => None,
+        }
+    }
+
+    pub fn set(&mut self, pos: Position<i32>, selected: bool) {
+        if let Some(i) = self.index(pos) {
+            self.bits[i] = selected;
+        }
+    }
+
+    pub fn contains(&self, pos: Position<i32>) -> bool {
+        self.index(pos).map(|i| self.bits[i]).unwrap_or(false)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.bits.iter().any(|b| *b)
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Positions -
+    //     Every selected position, in reading order
+    // -----------------------------------------------------------------------------
+    pub fn positions(&self) -> Vec<Position<i32>> {
+        let width = self.size.width;
+        self.bits
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b)
+            .map(|(i, _)| Position::new(i as i32 % width, i as i32 / width))
+            .collect()
+    }
+
+    // -----------------------------------------------------------------------------
+    //     - Bounds -
+    //     Origin and size of the smallest rectangle around the selected pixels
+    // -----------------------------------------------------------------------------
+    pub fn bounds(&self) -> Option<(Position<i32>, Size<i32>)> {
+        let positions = self.positions();
+        let min_x = positions.iter().map(|p| p.x).min()?;
+        let min_y = positions.iter().map(|p| p.y).min()?;
+        let max_x = positions.iter().map(|p| p.x).max()?;
+        let max_y = positions.iter().map(|p| p.y).max()?;
+
+        let origin = Position::new(min_x, min_y);
+        let size = Size::new(max_x - min_x + 1, max_y - min_y + 1);
+        Some((origin, size))
+    }
+
+    pub fn combine(&mut self, other: &Mask, op: MaskOp) {
+        let bits = self.bits.iter_mut().zip(&other.bits);
+        match op {
+            MaskOp::Replace => bits.for_each(|(a, b)| *a = *b),
+            MaskOp::Union => bits.for_each(|(a, b)| *a = *a || *b),
+            MaskOp::Subtract => bits.for_each(|(a, b)| *a = *a && !*b),
+            MaskOp::Intersect => bits.for_each(|(a, b)| *a = *a && *b),
+        }
+    }
+
+    pub fn invert(&mut self) {
+        self.bits.iter_mut().for_each(|b| *b = !*b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: i32, y: i32) -> Position<i32> {
+        Position::new(x, y)
+    }
+
+    fn mask(positions: &[Position<i32>]) -> Mask {
+        Mask::from_positions(Size::new(4, 4), positions)
+    }
+
+    #[test]
+    fn outside_is_never_selected() {
+        let mask = mask(&[pos(-1, 0), pos(4, 0), pos(3, 3)]);
+        assert_eq!(mask.positions(), vec![pos(3, 3)]);
+        assert!(!mask.contains(pos(-1, 0)));
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(mask(&[]).bounds(), None);
+
+        let (origin, size) = mask(&[pos(2, 1), pos(1, 3)]).bounds().unwrap();
+        assert_eq!(origin, pos(1, 1));
+        assert_eq!((size.width, size.height), (2, 3));
+    }
+
+    #[test]
+    fn combine() {
+        let a = mask(&[pos(0, 0), pos(1, 0)]);
+        let b = mask(&[pos(1, 0), pos(2, 0)]);
+
+        let combined = |op| {
+            let mut mask = mask(&a.positions());
+            mask.combine(&b, op);
+            mask.positions()
+        };
+
+        assert_eq!(combined(MaskOp::Replace), vec![pos(1, 0), pos(2, 0)]);
+        assert_eq!(combined(MaskOp::Union), vec![pos(0, 0), pos(1, 0), pos(2, 0)]);
+        assert_eq!(combined(MaskOp::Subtract), vec![pos(0, 0)]);
+        assert_eq!(combined(MaskOp::Intersect), vec![pos(1, 0)]);
+    }
+
+    #[test]
+    fn invert() {
+        let mut mask = mask(&[pos(0, 0)]);
+        mask.invert();
+        assert_eq!(mask.positions().len(), 15);
+        assert!(!mask.contains(pos(0, 0)));
+
+        mask.invert();
+        assert!(!mask.is_empty());
+        assert_eq!(mask.positions(), vec![pos(0, 0)]);
+    }
+}
@@ -0,0 +1,222 @@
+use nightmaregl::Position;
+
+// -----------------------------------------------------------------------------
+//     - Shape -
+//     Drawn between an anchor and the cursor
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Shape {
+    Line,
+    Rect { filled: bool },
+    Ellipse { filled: bool },
+}
+
+impl Shape {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "line" => Some(Shape::Line),
+            "rect" => Some(Shape::Rect { filled: false }),
+            "rectfill" => Some(Shape::Rect { filled: true }),
+            "ellipse" => Some(Shape::Ellipse { filled: false }),
+            "ellipsefill" => Some(Shape::Ellipse { filled: true }),
+            _ => None,
+        }
+    }
+
+    pub fn positions(&self, from: Position<i32>, to: Position<i32>) -> Vec<Position<i32>> {
+        match *self {
+            Shape::Line => line(from, to),
+            Shape::Rect { filled } => rect(from, to, filled),
+            Shape::Ellipse { filled } => ellipse(from, to, filled),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Line -
+//     Bresenham
+// -----------------------------------------------------------------------------
+pub fn line(from: Position<i32>, to: Position<i32>) -> Vec<Position<i32>> {
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let sx = if from.x < to.x { 1 } else { -1 };
+    let sy = if from.y < to.y { 1 } else { -1 };
+
+    let mut err = dx + dy;
+    let mut pos = from;
+    let mut positions = Vec::new();
+
+    loop {
+        positions.push(pos);
+
+        if pos == to {
+            break;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            pos.x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            pos.y += sy;
+        }
+    }
+
+    positions
+}
+
+// -----------------------------------------------------------------------------
+//     - Rectangle -
+// -----------------------------------------------------------------------------
+pub fn rect(from: Position<i32>, to: Position<i32>, filled: bool) -> Vec<Position<i32>> {
+    let (x0, x1) = (from.x.min(to.x), from.x.max(to.x));
+    let (y0, y1) = (from.y.min(to.y), from.y.max(to.y));
+
+    (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| Position::new(x, y)))
+        .filter(|p| filled || p.x == x0 || p.x == x1 || p.y == y0 || p.y == y1)
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+//     - Ellipse -
+//     Midpoint ellipse fitted inside the rectangle between the two points,
+//     so even widths and heights are symmetric as well
+//     (Alois Zingl: "A Rasterizing Algorithm for Drawing Curves").
+// -----------------------------------------------------------------------------
+pub fn ellipse(from: Position<i32>, to: Position<i32>, filled: bool) -> Vec<Position<i32>> {
+    let (mut x0, mut x1) = (from.x.min(to.x) as i64, from.x.max(to.x) as i64);
+    let (y0, y1) = (from.y.min(to.y) as i64, from.y.max(to.y) as i64);
+
+    let a = x1 - x0;
+    let b = y1 - y0;
+    let b1 = b & 1;
+
+    let mut dx = 4 * (1 - a) * b * b;
+    let mut dy = 4 * (b1 + 1) * a * a;
+    let mut err = dx + dy + b1 * a * a;
+
+    let mut y0 = y0 + (b + 1) / 2;
+    let mut y1 = y0 - b1;
+    let a8 = 8 * a * a;
+    let b8 = 8 * b * b;
+
+    let mut outline = Vec::new();
+    let mut plot = |x: i64, y: i64| outline.push(Position::new(x as i32, y as i32));
+
+    loop {
+        plot(x1, y0);
+        plot(x0, y0);
+        plot(x0, y1);
+        plot(x1, y1);
+
+        let e2 = 2 * err;
+        if e2 <= dy {
+            y0 += 1;
+            y1 -= 1;
+            dy += a8;
+            err += dy;
+        }
+        if e2 >= dx || 2 * err > dy {
+            x0 += 1;
+            x1 -= 1;
+            dx += b8;
+            err += dx;
+        }
+
+        if x0 > x1 {
+            break;
+        }
+    }
+
+    // Flat ellipses stop too early, finish the tips
+    while y0 - y1 <= b {
+        plot(x0 - 1, y0);
+        plot(x1 + 1, y0);
+        plot(x0 - 1, y1);
+        plot(x1 + 1, y1);
+        y0 += 1;
+        y1 -= 1;
+    }
+
+    outline.sort_by_key(|p| (p.y, p.x));
+    outline.dedup();
+
+    if !filled {
+        return outline;
+    }
+
+    // Fill every row between the left and right most outline pixel
+    let mut positions = Vec::new();
+    let mut rows = outline.iter().peekable();
+    while let Some(first) = rows.next() {
+        let mut last = first;
+        while let Some(next) = rows.peek() {
+            if next.y != first.y {
+                break;
+            }
+            last = rows.next().unwrap();
+        }
+        (first.x..=last.x).for_each(|x| positions.push(Position::new(x, first.y)));
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: i32, y: i32) -> Position<i32> {
+        Position::new(x, y)
+    }
+
+    #[test]
+    fn line_includes_both_ends() {
+        let positions = line(pos(0, 0), pos(5, 2));
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions.first(), Some(&pos(0, 0)));
+        assert_eq!(positions.last(), Some(&pos(5, 2)));
+
+        // Every step moves to a neighbour
+        for pair in positions.windows(2) {
+            assert!((pair[0].x - pair[1].x).abs() <= 1);
+            assert!((pair[0].y - pair[1].y).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn single_pixel() {
+        assert_eq!(line(pos(2, 2), pos(2, 2)), vec![pos(2, 2)]);
+        assert_eq!(rect(pos(2, 2), pos(2, 2), false), vec![pos(2, 2)]);
+    }
+
+    #[test]
+    fn rectangle() {
+        assert_eq!(rect(pos(3, 3), pos(0, 0), false).len(), 12);
+        assert_eq!(rect(pos(0, 0), pos(3, 3), true).len(), 16);
+    }
+
+    #[test]
+    fn ellipse_fits_and_is_symmetric() {
+        for (w, h) in [(7, 5), (8, 4), (6, 6), (9, 1)].iter() {
+            let outline = ellipse(pos(0, 0), pos(*w, *h), false);
+            for p in &outline {
+                assert!(p.x >= 0 && p.x <= *w && p.y >= 0 && p.y <= *h, "{:?}", p);
+                assert!(outline.contains(&pos(*w - p.x, p.y)));
+                assert!(outline.contains(&pos(p.x, *h - p.y)));
+            }
+
+            // Touches every side of the rectangle
+            assert!(outline.iter().any(|p| p.x == 0));
+            assert!(outline.iter().any(|p| p.x == *w));
+            assert!(outline.iter().any(|p| p.y == 0));
+            assert!(outline.iter().any(|p| p.y == *h));
+
+            let filled = ellipse(pos(0, 0), pos(*w, *h), true);
+            assert!(outline.iter().all(|p| filled.contains(p)));
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use nightmaregl::{Pixel, Position, Size};
+use serde_json::{json, Value};
+
+use crate::export::Image;
+use crate::frame::Tag;
+
+// -----------------------------------------------------------------------------
+//     - Layout -
+//     * Grid: every frame in a cell of the same size
+//     * Packed: frames trimmed to their pixels and packed in rows
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum Layout {
+    Grid,
+    Packed,
+}
+
+// -----------------------------------------------------------------------------
+//     - Json format -
+//     Aseprite's "hash" (frames keyed by name) or "array"
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub enum JsonFormat {
+    Hash,
+    Array,
+}
+
+// -----------------------------------------------------------------------------
+//     - Sheet options -
+//     `columns` is only used by the grid, 0 picks a square-ish grid.
+//     `padding` is the space around every frame, `extrude` repeats
+//     the edge pixels of every frame outwards (against texture bleeding).
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone)]
+pub struct SheetOptions {
+    pub layout: Layout,
+    pub columns: usize,
+    pub padding: i32,
+    pub extrude: i32,
+    pub json: JsonFormat,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        Self {
+            layout: Layout::Grid,
+            columns: 0,
+            padding: 0,
+            extrude: 0,
+            json: JsonFormat::Hash,
+        }
+    }
+}
+
+impl SheetOptions {
+    pub fn set_layout(&mut self, value: &str) -> Result<()> {
+        self.layout = match value {
+            "grid" => Layout::Grid,
+            "packed" => Layout::Packed,
+            _ => bail!("sheet is either grid or packed"),
+        };
+        Ok(())
+    }
+
+    pub fn set_json(&mut self, value: &str) -> Result<()> {
+        self.json = match value {
+            "hash" => JsonFormat::Hash,
+            "array" => JsonFormat::Array,
+            _ => bail!("sheetjson is either hash or array"),
+        };
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Cell -
+//     Where a frame ends up: `source` is the part of the frame that is kept
+//     (all of it, unless trimmed) and `at` is where it goes on the sheet.
+// -----------------------------------------------------------------------------
+struct Cell {
+    source: (Position<i32>, Size<i32>),
+    at: Position<i32>,
+}
+
+// -----------------------------------------------------------------------------
+//     - Export -
+//     Write the sheet as a png, with the metadata next to it
+//     (`sheet.png` and `sheet.json`).
+// -----------------------------------------------------------------------------
+pub fn export(
+    path: &Path,
+    size: Size<i32>,
+    images: &[Image],
+    tags: &[Tag],
+    options: &SheetOptions,
+) -> Result<()> {
+    let sources = images
+        .iter()
+        .map(|image| match options.layout {
+            Layout::Grid => (Position::zero(), size),
+            Layout::Packed => trim(image, size),
+        })
+        .collect::<Vec<_>>();
+
+    let (sheet_size, cells) = match options.layout {
+        Layout::Grid => grid(size, sources, options),
+        Layout::Packed => pack(sources, options),
+    };
+
+    let mut sheet = vec![Pixel::transparent(); (sheet_size.width * sheet_size.height) as usize];
+    for (image, cell) in images.iter().zip(&cells) {
+        blit(&mut sheet, sheet_size, image, size, cell, options.extrude);
+    }
+
+    write_png(path, sheet_size, &sheet)?;
+
+    let json = metadata(path, size, sheet_size, images, &cells, tags, options);
+    fs::write(path.with_extension("json"), serde_json::to_string_pretty(&json)?)?;
+
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - Trim -
+//     The rectangle around the pixels that aren't transparent.
+//     An empty frame is trimmed down to a single pixel.
+// -----------------------------------------------------------------------------
+fn trim(image: &Image, size: Size<i32>) -> (Position<i32>, Size<i32>) {
+    let painted = (0..size.height)
+        .flat_map(|y| (0..size.width).map(move |x| Position::new(x, y)))
+        .filter(|pos| image.pixels[(pos.y * size.width + pos.x) as usize].a > 0)
+        .collect::<Vec<_>>();
+
+    let min_x = painted.iter().map(|p| p.x).min();
+    let min_y = painted.iter().map(|p| p.y).min();
+    let max_x = painted.iter().map(|p| p.x).max();
+    let max_y = painted.iter().map(|p| p.y).max();
+
+    match (min_x, min_y, max_x, max_y) {
+        (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => (
+            Position::new(min_x, min_y),
+            Size::new(max_x - min_x + 1, max_y - min_y + 1),
+        ),
+        _ => (Position::zero(), Size::new(1, 1)),
+    }
+}
+
+fn grid(
+    size: Size<i32>,
+    sources: Vec<(Position<i32>, Size<i32>)>,
+    options: &SheetOptions,
+) -> (Size<i32>, Vec<Cell>) {
+    let count = sources.len();
+    let columns = match options.columns {
+        0 => (count as f32).sqrt().ceil() as usize,
+        columns => columns.min(count),
+    };
+    let rows = (count + columns - 1) / columns;
+
+    let (pad, extrude) = (options.padding, options.extrude);
+    let cell_width = size.width + extrude * 2 + pad;
+    let cell_height = size.height + extrude * 2 + pad;
+
+    let cells = sources
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| {
+            let (col, row) = ((i % columns) as i32, (i / columns) as i32);
+            let at = Position::new(
+                pad + col * cell_width + extrude,
+                pad + row * cell_height + extrude,
+            );
+            Cell { source, at }
+        })
+        .collect();
+
+    let sheet_size = Size::new(
+        pad + columns as i32 * cell_width,
+        pad + rows as i32 * cell_height,
+    );
+
+    (sheet_size, cells)
+}
+
+// -----------------------------------------------------------------------------
+//     - Pack -
+//     Tallest first, left to right in rows, aiming for a square sheet
+// -----------------------------------------------------------------------------
+fn pack(sources: Vec<(Position<i32>, Size<i32>)>, options: &SheetOptions) -> (Size<i32>, Vec<Cell>) {
+    let (pad, extrude) = (options.padding, options.extrude);
+    let outer = |size: Size<i32>| Size::new(size.width + extrude * 2 + pad, size.height + extrude * 2 + pad);
+
+    let area = sources
+        .iter()
+        .map(|(_, size)| outer(*size))
+        .map(|size| size.width * size.height)
+        .sum::<i32>();
+    let widest = sources.iter().map(|(_, size)| outer(*size).width).max().unwrap_or(0);
+    let max_width = widest.max((area as f32).sqrt().ceil() as i32) + pad;
+
+    let mut order = (0..sources.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| -sources[*i].1.height);
+
+    let mut cells = sources
+        .iter()
+        .map(|source| Cell {
+            source: *source,
+            at: Position::zero(),
+        })
+        .collect::<Vec<_>>();
+
+    let (mut x, mut y, mut row_height) = (pad, pad, 0);
+    let mut width = 0;
+
+    for i in order {
+        let size = outer(cells[i].source.1);
+        if x > pad && x + size.width > max_width {
+            x = pad;
+            y += row_height;
+            row_height = 0;
+        }
+
+        cells[i].at = Position::new(x + extrude, y + extrude);
+        x += size.width;
+        row_height = row_height.max(size.height);
+        width = width.max(x);
+    }
+
+    (Size::new(width, y + row_height), cells)
+}
+
+// -----------------------------------------------------------------------------
+//     - Blit -
+//     Copy the frame onto the sheet, extruding the edges
+// -----------------------------------------------------------------------------
+fn blit(
+    sheet: &mut [Pixel],
+    sheet_size: Size<i32>,
+    image: &Image,
+    size: Size<i32>,
+    cell: &Cell,
+    extrude: i32,
+) {
+    let (origin, source) = cell.source;
+
+    for y in -extrude..source.height + extrude {
+        for x in -extrude..source.width + extrude {
+            let sx = origin.x + x.clamp(0, source.width - 1);
+            let sy = origin.y + y.clamp(0, source.height - 1);
+            let pixel = image.pixels[(sy * size.width + sx) as usize];
+
+            let (dx, dy) = (cell.at.x + x, cell.at.y + y);
+            sheet[(dy * sheet_size.width + dx) as usize] = pixel;
+        }
+    }
+}
+
+fn write_png(path: &Path, size: Size<i32>, pixels: &[Pixel]) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.width as u32, size.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let bytes = pixels.iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect::<Vec<_>>();
+    encoder.write_header()?.write_image_data(&bytes)?;
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - Metadata -
+//     The same layout as Aseprite's json export, so existing importers
+//     (Phaser, Godot, Unity plugins...) can read it.
+// -----------------------------------------------------------------------------
+fn metadata(
+    path: &Path,
+    size: Size<i32>,
+    sheet_size: Size<i32>,
+    images: &[Image],
+    cells: &[Cell],
+    tags: &[Tag],
+    options: &SheetOptions,
+) -> Value {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("sheet");
+    let image = path.file_name().and_then(|s| s.to_str()).unwrap_or("sheet.png");
+
+    let frames = images.iter().zip(cells).enumerate().map(|(i, (frame, cell))| {
+        let (origin, source) = cell.source;
+        let name = format!("{} {}", stem, i);
+        let value = json!({
+            "frame": { "x": cell.at.x, "y": cell.at.y, "w": source.width, "h": source.height },
+            "rotated": false,
+            "trimmed": source.width != size.width || source.height != size.height,
+            "spriteSourceSize": { "x": origin.x, "y": origin.y, "w": source.width, "h": source.height },
+            "sourceSize": { "w": size.width, "h": size.height },
+            "duration": frame.duration,
+        });
+        (name, value)
+    });
+
+    let frames = match options.json {
+        JsonFormat::Hash => Value::Object(frames.collect()),
+        JsonFormat::Array => Value::Array(
+            frames
+                .map(|(name, mut value)| {
+                    value["filename"] = json!(name);
+                    value
+                })
+                .collect(),
+        ),
+    };
+
+    let frame_tags = tags
+        .iter()
+        .map(|tag| {
+            json!({
+                "name": tag.name,
+                "from": tag.from,
+                "to": tag.to,
+                "direction": tag.direction.as_str(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "frames": frames,
+        "meta": {
+            "app": "mixel",
+            "version": env!("CARGO_PKG_VERSION"),
+            "image": image,
+            "format": "RGBA8888",
+            "size": { "w": sheet_size.width, "h": sheet_size.height },
+            "scale": "1",
+            "frameTags": frame_tags,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(padding: i32, extrude: i32) -> SheetOptions {
+        SheetOptions {
+            padding,
+            extrude,
+            ..SheetOptions::default()
+        }
+    }
+
+    fn sources(sizes: &[(i32, i32)]) -> Vec<(Position<i32>, Size<i32>)> {
+        sizes.iter().map(|(w, h)| (Position::zero(), Size::new(*w, *h))).collect()
+    }
+
+    #[test]
+    fn grid_is_square_ish() {
+        let size = Size::new(4, 4);
+        let (sheet, cells) = grid(size, sources(&[(4, 4); 5]), &options(0, 0));
+        assert_eq!((sheet.width, sheet.height), (12, 8));
+        assert_eq!(cells[4].at, Position::new(4, 4));
+    }
+
+    #[test]
+    fn grid_with_padding_and_extrude() {
+        let size = Size::new(4, 4);
+        let options = SheetOptions {
+            columns: 2,
+            ..options(1, 1)
+        };
+
+        let (sheet, cells) = grid(size, sources(&[(4, 4); 3]), &options);
+        assert_eq!((sheet.width, sheet.height), (15, 15));
+        assert_eq!(cells[0].at, Position::new(2, 2));
+        assert_eq!(cells[2].at, Position::new(2, 9));
+    }
+
+    #[test]
+    fn packed_frames_do_not_overlap() {
+        let options = options(1, 1);
+        let (sheet, cells) = pack(sources(&[(3, 5), (8, 2), (1, 1), (4, 4), (2, 6)]), &options);
+
+        // The rectangles with the extruded edges
+        let rects = cells
+            .iter()
+            .map(|cell| {
+                let size = cell.source.1;
+                (cell.at.x - 1, cell.at.y - 1, size.width + 2, size.height + 2)
+            })
+            .collect::<Vec<_>>();
+
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.0 >= 1 && a.1 >= 1);
+            assert!(a.0 + a.2 <= sheet.width && a.1 + a.3 <= sheet.height);
+
+            for b in &rects[i + 1..] {
+                let apart = a.0 + a.2 <= b.0 || b.0 + b.2 <= a.0 || a.1 + a.3 <= b.1 || b.1 + b.3 <= a.1;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn trimmed_to_the_painted_pixels() {
+        let size = Size::new(4, 4);
+        let mut image = Image {
+            pixels: vec![Pixel::transparent(); 16],
+            duration: 100,
+        };
+
+        let (origin, trimmed) = trim(&image, size);
+        assert_eq!(origin, Position::zero());
+        assert_eq!((trimmed.width, trimmed.height), (1, 1));
+
+        // (2, 1) and (1, 3)
+        image.pixels[6] = Pixel { r: 0, g: 0, b: 0, a: 255 };
+        image.pixels[13] = Pixel { r: 0, g: 0, b: 0, a: 255 };
+        let (origin, trimmed) = trim(&image, size);
+        assert_eq!(origin, Position::new(1, 1));
+        assert_eq!((trimmed.width, trimmed.height), (2, 3));
+    }
+}
@@ -0,0 +1,98 @@
+use nightmaregl::{Pixel, Size};
+
+use crate::color;
+use crate::export::Image;
+use crate::history::{Change, Edit};
+
+const FRAME_DURATION: u32 = 100;
+const FINAL_DURATION: u32 = 1000;
+
+// -----------------------------------------------------------------------------
+//     - Replay -
+//     Rewind copies of the frames (one pixel buffer per layer) past every edit,
+//     then play the edits back, keeping an image every `every` edits
+//     and of the end result.
+//     Each image is the frame the last edit was made on, flattened.
+// -----------------------------------------------------------------------------
+pub fn replay(
+    mut frames: Vec<Vec<Vec<Pixel>>>,
+    size: Size<i32>,
+    edits: &[Edit],
+    every: usize,
+) -> Vec<Image> {
+    // Linking cels changes no pixels
+    let edits = edits.iter().filter(|e| !e.changes.is_empty()).collect::<Vec<_>>();
+
+    let index = |change: &Change| {
+        (change.position.y * size.width + change.position.x) as usize
+    };
+
+    for edit in edits.iter().rev() {
+        for change in edit.changes.iter().rev() {
+            frames[change.frame][change.layer][index(change)] = change.before;
+        }
+    }
+
+    let first_frame = edits.first().map(|e| e.changes[0].frame).unwrap_or(0);
+    let mut images = vec![flatten(&frames[first_frame], FRAME_DURATION)];
+
+    for (i, edit) in edits.iter().enumerate() {
+        for change in &edit.changes {
+            frames[change.frame][change.layer][index(change)] = change.after;
+        }
+
+        let last = i + 1 == edits.len();
+        if (i + 1) % every == 0 || last {
+            let duration = match last {
+                true => FINAL_DURATION,
+                false => FRAME_DURATION,
+            };
+            images.push(flatten(&frames[edit.changes[0].frame], duration));
+        }
+    }
+
+    images
+}
+
+fn flatten(layers: &[Vec<Pixel>], duration: u32) -> Image {
+    let pixels = (0..layers[0].len())
+        .map(|i| {
+            layers
+                .iter()
+                .fold(Pixel::transparent(), |dst, layer| color::blend(dst, layer[i]))
+        })
+        .collect();
+
+    Image { pixels, duration }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nightmaregl::Position;
+
+    #[test]
+    fn replay_every_other_edit() {
+        let red = Pixel { r: 255, g: 0, b: 0, a: 255 };
+        let t = Pixel::transparent();
+
+        let edits = (0..3)
+            .map(|x| {
+                let mut edit = Edit::new();
+                edit.push((0, 0), Position::new(x, 0), t, red);
+                edit
+            })
+            .collect::<Vec<_>>();
+
+        // The frames as they are after every edit
+        let frames = vec![vec![vec![red; 3]]];
+        let images = replay(frames, Size::new(3, 1), &edits, 2);
+
+        let painted = images
+            .iter()
+            .map(|i| i.pixels.iter().filter(|p| **p == red).count())
+            .collect::<Vec<_>>();
+        assert_eq!(painted, vec![0, 2, 3]);
+        assert_eq!(images.last().map(|i| i.duration), Some(FINAL_DURATION));
+    }
+}